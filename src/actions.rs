@@ -1,27 +1,116 @@
 use std::{
     cmp::Ordering,
-    fs,
-    path::PathBuf,
-    sync::{Arc, RwLock},
-    time::Duration,
+    collections::VecDeque,
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
 };
 
-use anyhow::{Ok, Result};
-use crossterm::event::{self, read, KeyCode, KeyEventKind};
+use anyhow::{anyhow, Ok, Result};
+use crossterm::event::{
+    self, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+};
+use ratatui::layout::{Margin, Position};
 
 use crate::{
-    player::Player,
-    workspace::{Saver, TreeState, Windows, Workspace, PLAYLIST_FILE_EXT},
+    command,
+    formats::{PlaylistFormat, PlaylistTrack},
+    history::{Applied, Change},
+    keymap::{Context, KeyState, Resolved},
+    library,
+    player::{Player, SortKey},
+    smart::SmartPlaylist,
+    workspace::{
+        Playlist, PlaylistEditor, PlaylistKind, Saver, TreeState, Windows, Workspace,
+        PLAYLIST_FILE_EXT,
+    },
 };
 
 pub const MUSIC_EXTENSIONS: [&str; 3] = ["mp3", "wav", "ogg"];
 
+// rows moved by one step of the mouse wheel
+const SCROLL_LINES: usize = 3;
+// second click on the same row within this is a double click
+const DOUBLE_CLICK: Duration = Duration::from_millis(400);
+// keys, ctl and MPRIS run actions one at a time, so the queue snapshot of an action
+// doesn't catch edits from another thread, actions that prompt don't take it
+static PERFORMING: Mutex<()> = Mutex::new(());
+
+// every action a key or the command line can name, from_str and the config check use these
+const ACTIONS: [(&str, Action); 44] = [
+    ("Escape", Action::Escape),
+    ("Up", Action::Up),
+    ("Down", Action::Down),
+    ("Top", Action::Top),
+    ("Bottom", Action::Bottom),
+    ("ParentDir", Action::ParentDir),
+    ("Exit", Action::Exit),
+    ("Select", Action::Select),
+    ("ToggleTreeView", Action::ToggleTreeView),
+    ("ToggleTreeViewBack", Action::ToggleTreeViewBack),
+    ("ClearQueue", Action::ClearQueue),
+    ("AddToQueue", Action::AddToQueue),
+    ("AddAllToQueue", Action::AddAllToQueue),
+    ("TogglePause", Action::TogglePause),
+    ("Play", Action::Play),
+    ("Pause", Action::Pause),
+    ("ToggleRepeat", Action::ToggleRepeat),
+    ("Skip", Action::Skip),
+    ("SelectTheme", Action::SelectTheme),
+    ("Delete", Action::Delete),
+    ("PlaylistSave", Action::PlaylistSave),
+    ("ExportPlaylist", Action::ExportPlaylist),
+    ("EditPlaylist", Action::EditPlaylist),
+    ("ClosePlaylist", Action::ClosePlaylist),
+    ("AppendToPlaylist", Action::AppendToPlaylist),
+    ("RenamePlaylist", Action::RenamePlaylist),
+    ("DuplicatePlaylist", Action::DuplicatePlaylist),
+    ("MoveUp", Action::MoveUp),
+    ("MoveDown", Action::MoveDown),
+    ("MoveToTop", Action::MoveToTop),
+    ("PlayNext", Action::PlayNext),
+    ("SortQueueByPath", Action::SortQueueByPath),
+    ("SortQueueByTag", Action::SortQueueByTag),
+    ("SortQueueByTrack", Action::SortQueueByTrack),
+    ("MissingTracks", Action::MissingTracks),
+    ("RelinkPrefix", Action::RelinkPrefix),
+    ("RelinkSearch", Action::RelinkSearch),
+    ("ToggleMark", Action::ToggleMark),
+    ("VisualMode", Action::VisualMode),
+    ("Undo", Action::Undo),
+    ("Redo", Action::Redo),
+    ("ReloadConfig", Action::ReloadConfig),
+    ("Help", Action::Help),
+    ("Command", Action::Command),
+];
+// variant constructor that takes the arg
+type ArgAction = fn(usize) -> Action;
+const ARG_ACTIONS: [(&str, ArgAction); 6] = [
+    ("RewindForward", Action::RewindForward),
+    ("RewindBack", Action::RewindBack),
+    ("VolumeDecrease", Action::VolumeDecrease),
+    ("VolumeIncrease", Action::VolumeIncrease),
+    ("Seek", Action::Seek),
+    ("SetVolume", Action::SetVolume),
+];
+
+pub fn action_names() -> Vec<&'static str> {
+    ACTIONS.iter().map(|(name, _)| *name).collect()
+}
+
+pub fn arg_action_names() -> Vec<&'static str> {
+    ARG_ACTIONS.iter().map(|(name, _)| *name).collect()
+}
+
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum Action {
     None,
     Escape,
     Up,
     Down,
+    Top,
+    Bottom,
     ParentDir,
     Exit,
     Select,
@@ -31,112 +120,326 @@ pub enum Action {
     AddToQueue,
     AddAllToQueue,
     TogglePause,
+    Play,
+    Pause,
     ToggleRepeat,
     Skip,
     RewindForward(usize),
     RewindBack(usize),
     VolumeDecrease(usize),
     VolumeIncrease(usize),
+    // position in seconds
+    Seek(usize),
+    // percent
+    SetVolume(usize),
     SelectTheme,
     Delete,
     PlaylistSave,
+    ExportPlaylist,
+    EditPlaylist,
+    ClosePlaylist,
+    AppendToPlaylist,
+    RenamePlaylist,
+    DuplicatePlaylist,
+    MoveUp,
+    MoveDown,
+    MoveToTop,
+    PlayNext,
+    SortQueueByPath,
+    SortQueueByTag,
+    SortQueueByTrack,
+    MissingTracks,
+    RelinkPrefix,
+    RelinkSearch,
+    ToggleMark,
+    VisualMode,
+    Undo,
+    Redo,
+    ReloadConfig,
+    Help,
+    Command,
+    // only given by the command line
+    LoadPlaylist(PathBuf),
+    ChangeDir(PathBuf),
+    // files, directories and playlists from outside, see tracks_from_paths
+    Enqueue(Vec<PathBuf>),
+    EnqueueNext(Vec<PathBuf>),
+    Open(Vec<PathBuf>),
+}
+
+// name like in config, with argument and its unit, for help
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = format!("{:?}", self);
+        let name = name.split('(').next().unwrap_or_default();
+
+        match self {
+            Self::RewindForward(x) | Self::RewindBack(x) => write!(f, "{} {}s", name, x),
+            Self::VolumeDecrease(x) | Self::VolumeIncrease(x) | Self::SetVolume(x) => {
+                write!(f, "{} {}%", name, x)
+            }
+            Self::Seek(x) => write!(f, "{} {}s", name, x),
+            _ => write!(f, "{}", name),
+        }
+    }
 }
 
 impl Action {
     pub fn from_str(action_str: &str) -> Option<Self> {
-        match action_str {
-            "Escape" => Some(Action::Escape),
-            "Up" => Some(Action::Up),
-            "Down" => Some(Action::Down),
-            "ParentDir" => Some(Action::ParentDir),
-            "Exit" => Some(Action::Exit),
-            "Select" => Some(Action::Select),
-            "ToggleTreeView" => Some(Action::ToggleTreeView),
-            "ToggleTreeViewBack" => Some(Action::ToggleTreeViewBack),
-            "ClearQueue" => Some(Action::ClearQueue),
-            "AddToQueue" => Some(Action::AddToQueue),
-            "AddAllToQueue" => Some(Action::AddAllToQueue),
-            "TogglePause" => Some(Action::TogglePause),
-            "ToggleRepeat" => Some(Action::ToggleRepeat),
-            "Skip" => Some(Action::Skip),
-            "SelectTheme" => Some(Action::SelectTheme),
-            "Delete" => Some(Action::Delete),
-            "PlaylistSave" => Some(Action::PlaylistSave),
+        ACTIONS
+            .iter()
+            .find(|(name, _)| *name == action_str)
+            .map(|(_, action)| action.clone())
+    }
+
+    // same action with another argument, None for actions without one
+    pub fn with_arg(&self, arg: usize) -> Option<Self> {
+        match self {
+            Self::RewindForward(_) => Some(Self::RewindForward(arg)),
+            Self::RewindBack(_) => Some(Self::RewindBack(arg)),
+            Self::VolumeDecrease(_) => Some(Self::VolumeDecrease(arg)),
+            Self::VolumeIncrease(_) => Some(Self::VolumeIncrease(arg)),
+            Self::Seek(_) => Some(Self::Seek(arg)),
+            Self::SetVolume(_) => Some(Self::SetVolume(arg)),
             _ => None,
         }
     }
 
     pub fn from_str_arg<T: Into<usize>>(action_str: &str, arg: T) -> Option<Self> {
-        match action_str {
-            "RewindForward" => Some(Action::RewindForward(arg.into())),
-            "RewindBack" => Some(Action::RewindBack(arg.into())),
-            "VolumeDecrease" => Some(Action::VolumeDecrease(arg.into())),
-            "VolumeIncrease" => Some(Action::VolumeIncrease(arg.into())),
-            _ => None,
-        }
+        ARG_ACTIONS
+            .iter()
+            .find(|(name, _)| *name == action_str)
+            .map(|(_, action)| action(arg.into()))
+    }
+
+    // actions that wait for an answer typed in the terminal
+    pub fn prompts(&self) -> bool {
+        matches!(
+            self,
+            Self::PlaylistSave
+                | Self::EditPlaylist
+                | Self::ClosePlaylist
+                | Self::RenamePlaylist
+                | Self::DuplicatePlaylist
+                | Self::RelinkPrefix
+        )
     }
 
-    fn perform_action(
+    // ctl and MPRIS can't answer a prompt, and their thread would read keys
+    // meant for the main loop
+    pub fn perform_remote(
         &self,
         workspace: Arc<RwLock<Workspace>>,
         player: Arc<RwLock<Player>>,
+        count: usize,
     ) -> Result<()> {
-        let current_window = workspace.read().unwrap().window.clone();
+        if self.prompts() {
+            return Err(anyhow!("{} asks for input, run it in musicshell", self));
+        }
+        self.perform_action(workspace, player, count)
+    }
 
-        match self {
-            Self::Up if current_window == Windows::None => Self::up(&workspace, &player),
+    // queue changes of every action are recorded, so any of them can be undone,
+    // an action repeated with a count is undone at once
+    pub fn perform_action(
+        &self,
+        workspace: Arc<RwLock<Workspace>>,
+        player: Arc<RwLock<Player>>,
+        count: usize,
+    ) -> Result<()> {
+        // ctl and MPRIS don't wait while a prompt is open,
+        // the only queue change after a prompt is recorded by relink
+        if self.prompts() {
+            for _ in 0..count {
+                self.dispatch(Arc::clone(&workspace), Arc::clone(&player))?;
+            }
+            return Ok(());
+        }
 
-            Self::Down if current_window == Windows::None => Self::down(&workspace, &player),
+        let _performing = PERFORMING.lock().unwrap_or_else(|e| e.into_inner());
+        // repeated clears would all land before the player moves on
+        if *self == Self::Skip {
+            player.write().unwrap().skip(count);
+            return Ok(());
+        }
 
-            Self::Select if current_window == Windows::None => {
-                Self::select_tree(&workspace, &player)?
+        let before = player.read().unwrap().queue.clone();
+        let mut result = Ok(());
+        for _ in 0..count {
+            result = self.dispatch(Arc::clone(&workspace), Arc::clone(&player));
+            if result.is_err() {
+                break;
             }
+        }
+        let after = player.read().unwrap().queue.clone();
+
+        // relinking records the queue itself, it may run after a prompt
+        let recorded = matches!(
+            self,
+            Self::Undo | Self::Redo | Self::MissingTracks | Self::RelinkSearch
+        );
+        if before != after && !recorded {
+            workspace
+                .write()
+                .unwrap()
+                .history
+                .push(Change::Queue { before, after });
+        }
 
-            Self::ParentDir if current_window == Windows::None => Self::parent_dir(&workspace)?,
+        result
+    }
 
-            Self::ToggleTreeView if current_window == Windows::None => {
-                Self::toggle_tree_view(&workspace, false)
+    // popups take their own actions and the ones that don't depend on a view,
+    // the rest only works with one of the views shown
+    fn dispatch(
+        &self,
+        workspace: Arc<RwLock<Workspace>>,
+        player: Arc<RwLock<Player>>,
+    ) -> Result<()> {
+        let (context, current_window) = {
+            let mutex = workspace.read().unwrap();
+            (Context::current(&mutex), mutex.window.clone())
+        };
+
+        match (context, self) {
+            // player
+            (_, Self::ClearQueue) => Self::clear_queue(&player),
+            (_, Self::Skip) => player.write().unwrap().skip(1),
+            (_, Self::VolumeDecrease(x)) => Self::change_volume(&player, *x as f32, false),
+            (_, Self::VolumeIncrease(x)) => Self::change_volume(&player, *x as f32, true),
+            (_, Self::TogglePause) => Self::toggle_pause(&player),
+            (_, Self::Play) => player.read().unwrap().resume(),
+            (_, Self::Pause) => player.read().unwrap().pause(),
+            (_, Self::SetVolume(x)) => player
+                .read()
+                .unwrap()
+                .set_volume((*x).min(100) as f32 / 100.0),
+
+            (_, Self::RewindForward(x)) => Self::rewind(&player, *x as u64, true),
+
+            (_, Self::RewindBack(x)) => Self::rewind(&player, *x as u64, false),
+
+            (_, Self::Seek(x)) => player.read().unwrap().seek(*x as u64),
+
+            (_, Self::LoadPlaylist(path)) => Self::load_playlist_path(&workspace, &player, path)?,
+            (_, Self::Enqueue(paths)) => {
+                let tracks = Self::tracks_from_paths(&workspace, &player, paths)?;
+                player.write().unwrap().queue.extend(tracks);
             }
-            Self::ToggleTreeViewBack if current_window == Windows::None => {
-                Self::toggle_tree_view(&workspace, true)
+            (_, Self::EnqueueNext(paths)) => {
+                let tracks = Self::tracks_from_paths(&workspace, &player, paths)?;
+                player.write().unwrap().play_next(tracks.into());
             }
-
-            Self::AddToQueue if current_window == Windows::None => {
-                Self::add_to_queue(&workspace, &player)
+            (_, Self::Open(paths)) => {
+                let tracks = Self::tracks_from_paths(&workspace, &player, paths)?;
+                player.write().unwrap().play_queue(tracks);
             }
-            Self::ClearQueue => Self::clear_queue(&player),
-
-            Self::Delete if current_window == Windows::None => Self::delete(&workspace, &player)?,
-            Self::Skip => Self::skip(&player),
-            Self::VolumeDecrease(x) => Self::change_volume(&player, *x as f32, false),
-            Self::VolumeIncrease(x) => Self::change_volume(&player, *x as f32, true),
-            Self::TogglePause => Self::toggle_pause(&player),
-
-            Self::RewindForward(x) => Self::rewind(&player, *x as u64, true),
 
-            Self::RewindBack(x) => Self::rewind(&player, *x as u64, false),
+            (_, Self::ToggleRepeat) => Self::toggle_repeat(&player),
 
-            Self::ToggleRepeat => Self::toggle_repeat(&player),
+            (_, Self::Escape) => Self::escape(&workspace),
 
-            Self::Escape => Self::escape(&workspace),
+            (_, Self::SortQueueByPath) => Self::sort_queue(&workspace, &player, SortKey::Path),
+            (_, Self::SortQueueByTag) => Self::sort_queue(&workspace, &player, SortKey::Tag),
+            (_, Self::SortQueueByTrack) => {
+                Self::sort_queue(&workspace, &player, SortKey::TrackNumber)
+            }
 
             // theme selection Window
-            Self::SelectTheme if current_window == Windows::None => {
-                Self::show_select_theme(&workspace)
+            (Context::ThemeSelect, Self::Up) => Self::up_select_theme(&workspace),
+            (Context::ThemeSelect, Self::Down) => Self::down_select_theme(&workspace),
+            (Context::ThemeSelect, Self::Select) => Self::select_theme(&workspace),
+
+            // missing tracks report
+            (Context::Report, Self::Up) => {
+                let mut mutex = workspace.write().unwrap();
+                mutex.popup_selected = mutex.popup_selected.saturating_sub(1);
             }
-            Self::Up if current_window == Windows::ThemeSelect => Self::up_select_theme(&workspace),
-            Self::Down if current_window == Windows::ThemeSelect => {
-                Self::down_select_theme(&workspace)
+            (Context::Report, Self::Down) => {
+                let mut mutex = workspace.write().unwrap();
+                if let Windows::Report(_, lines) = &current_window
+                    && mutex.popup_selected + 1 < lines.len()
+                {
+                    mutex.popup_selected += 1;
+                }
             }
 
-            Self::Select if current_window == Windows::ThemeSelect => {
-                Self::select_theme(&workspace)
+            (context, action) if context.is_view() => action.dispatch_view(&workspace, &player)?,
+
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn dispatch_view(
+        &self,
+        workspace: &Arc<RwLock<Workspace>>,
+        player: &Arc<RwLock<Player>>,
+    ) -> Result<()> {
+        match self {
+            Self::Up => Self::up(workspace, player),
+
+            Self::Down => Self::down(workspace, player),
+
+            Self::Top => Self::top(workspace),
+
+            Self::Bottom => Self::bottom(workspace, player),
+
+            Self::Select => Self::select_tree(workspace, player)?,
+
+            Self::ParentDir => Self::parent_dir(workspace)?,
+
+            Self::ToggleTreeView => Self::toggle_tree_view(workspace, false),
+            Self::ToggleTreeViewBack => Self::toggle_tree_view(workspace, true),
+
+            Self::AddToQueue => Self::add_to_queue(workspace, player),
+            Self::AddAllToQueue => Self::add_all_to_queue(workspace, player),
+
+            Self::Delete => Self::delete(workspace, player)?,
+
+            Self::ReloadConfig => Self::reload_config(workspace)?,
+
+            Self::SelectTheme => Self::show_select_theme(workspace),
+
+            Self::Help => Self::show_help(workspace),
+
+            // command line
+            Self::Command => Self::show_command(workspace),
+            Self::ChangeDir(dir) => {
+                Self::open_dir(workspace, dir.clone())?;
+                workspace.write().unwrap().tree.state = TreeState::Files;
             }
 
             // playlists save
-            Self::PlaylistSave if current_window == Windows::None => {
-                Self::save_playlist(&workspace, &player)?
-            }
+            Self::PlaylistSave => Self::save_playlist(workspace, player)?,
+            Self::ExportPlaylist => Self::export_playlist(workspace)?,
+
+            // playlist editor
+            Self::EditPlaylist => Self::edit_playlist(workspace)?,
+            Self::ClosePlaylist => Self::close_playlist(workspace)?,
+            Self::AppendToPlaylist => Self::append_to_playlist(workspace)?,
+            Self::RenamePlaylist => Self::rename_playlist(workspace)?,
+            Self::DuplicatePlaylist => Self::duplicate_playlist(workspace)?,
+            Self::MoveUp => Self::move_track(workspace, player, true),
+            Self::MoveDown => Self::move_track(workspace, player, false),
+            Self::MoveToTop => Self::move_to_top(workspace, player),
+
+            // queue editing
+            Self::PlayNext => Self::play_next(workspace, player),
+
+            // history
+            Self::Undo => Self::undo(workspace, player, true)?,
+            Self::Redo => Self::undo(workspace, player, false)?,
+
+            // multi-select
+            Self::ToggleMark => Self::toggle_mark(workspace),
+            Self::VisualMode => Self::visual_mode(workspace),
+
+            // missing tracks report
+            Self::MissingTracks => Self::relink(workspace, player, |_| 0)?,
+            Self::RelinkPrefix => Self::relink_prefix(workspace, player)?,
+            Self::RelinkSearch => Self::relink_search(workspace, player)?,
 
             _ => {}
         }
@@ -157,15 +460,7 @@ impl Action {
     fn down(workspace: &Arc<RwLock<Workspace>>, player: &Arc<RwLock<Player>>) {
         let (mut selected, list_len) = {
             let mutex = workspace.read().unwrap();
-            let list_len = if mutex.tree.state == TreeState::Files {
-                mutex.tree.path_list.len()
-            } else if mutex.tree.state == TreeState::Queue {
-                player.read().unwrap().queue.len()
-            } else {
-                mutex.tree.playlists.len()
-            };
-
-            (mutex.tree.selected, list_len)
+            (mutex.tree.selected, Self::list_len(&mutex, player))
         };
 
         if list_len != 0 && selected < list_len - 1 {
@@ -174,6 +469,32 @@ impl Action {
         workspace.write().unwrap().tree.selected = selected;
     }
 
+    fn top(workspace: &Arc<RwLock<Workspace>>) {
+        workspace.write().unwrap().tree.selected = 0;
+    }
+
+    fn bottom(workspace: &Arc<RwLock<Workspace>>, player: &Arc<RwLock<Player>>) {
+        let mut mutex = workspace.write().unwrap();
+        mutex.tree.selected = Self::list_len(&mutex, player).saturating_sub(1);
+    }
+
+    // length of the list in current view
+    fn list_len(workspace: &Workspace, player: &Arc<RwLock<Player>>) -> usize {
+        if workspace.tree.state == TreeState::Files {
+            workspace.tree.path_list.len()
+        } else if workspace.tree.state == TreeState::Queue {
+            player.read().unwrap().queue.len()
+        } else if workspace.tree.state == TreeState::Editor {
+            workspace
+                .editor
+                .as_ref()
+                .map(|x| x.tracks.len())
+                .unwrap_or(0)
+        } else {
+            workspace.tree.playlists.len()
+        }
+    }
+
     fn select_tree(workspace: &Arc<RwLock<Workspace>>, player: &Arc<RwLock<Player>>) -> Result<()> {
         let mutex = workspace.read().unwrap();
         if mutex.tree.state == TreeState::Files {
@@ -190,18 +511,8 @@ impl Action {
             if element.is_dir() {
                 // if we are in files and at
                 // directory
-                let mut new_list: Vec<PathBuf> = element
-                    .read_dir()?
-                    .filter_map(|entry| entry.ok().map(|e| e.path()))
-                    .collect();
-                new_list.sort_paths();
-
                 drop(mutex);
-                let mut mutex = workspace.write().unwrap();
-                mutex.tree.path_list = new_list;
-                mutex.tree.selected = 0;
-                mutex.tree.cwd = element;
-                return Ok(());
+                return Self::open_dir(workspace, element);
             } else if element.is_file()                      // if we are in files and at
                                                                      // a music file
                         && element.extension().is_some()
@@ -219,17 +530,25 @@ impl Action {
                 return Ok(());
             }
         } else if mutex.tree.state == TreeState::Playlists {
-            let selected = {
-                let mutex = workspace.read().unwrap();
-                let selected = mutex.tree.selected;
+            let Some(selected) = mutex.tree.playlists.get(mutex.tree.selected).cloned() else {
+                return Ok(());
+            };
 
-                mutex.tree.playlists[selected].clone()
+            drop(mutex);
+            Self::load_playlist(workspace, player, &selected)?;
+        } else if mutex.tree.state == TreeState::Editor {
+            // play the track right away, like in files
+            let Some(track) = mutex
+                .editor
+                .as_ref()
+                .and_then(|x| x.tracks.get(mutex.tree.selected))
+            else {
+                return Ok(());
             };
 
-            player.write().unwrap().song_index = 0;
-            player.write().unwrap().clear();
-            player.write().unwrap().queue =
-                Saver::restore_playlist(&selected, &mutex.config.playlists_folder)?;
+            let mut player = player.write().unwrap();
+            player.queue.push_front(track.path.clone());
+            player.restart();
         } else {
             // if in queue
             let index = mutex.tree.selected;
@@ -255,6 +574,10 @@ impl Action {
             option.unwrap().to_path_buf()
         };
 
+        Self::open_dir(workspace, dir)
+    }
+
+    fn open_dir(workspace: &Arc<RwLock<Workspace>>, dir: PathBuf) -> Result<()> {
         let mut new_list: Vec<PathBuf> = dir
             .read_dir()?
             .filter_map(|entry| entry.ok().map(|e| e.path()))
@@ -264,42 +587,186 @@ impl Action {
         let mut mutex = workspace.write().unwrap();
         mutex.tree.path_list = new_list;
         mutex.tree.selected = 0;
-        mutex.tree.cwd = dir.to_path_buf();
+        mutex.tree.cwd = dir;
+        mutex.tree.clear_marks();
 
         Ok(())
     }
 
+    // replaces the queue
+    fn load_playlist(
+        workspace: &Arc<RwLock<Workspace>>,
+        player: &Arc<RwLock<Player>>,
+        selected: &Playlist,
+    ) -> Result<()> {
+        let queue = Self::playlist_tracks(workspace, player, &selected.path, &selected.kind)?;
+
+        let report = library::missing_report(&queue);
+        player.write().unwrap().play_queue(queue);
+
+        if let Some(report) = report {
+            workspace.write().unwrap().window = Windows::Error(report);
+        }
+
+        Ok(())
+    }
+
+    // smart playlists are built from the library every time they are opened
+    fn playlist_tracks(
+        workspace: &Arc<RwLock<Workspace>>,
+        player: &Arc<RwLock<Player>>,
+        path: &Path,
+        kind: &PlaylistKind,
+    ) -> Result<VecDeque<PathBuf>> {
+        if *kind == PlaylistKind::Smart {
+            let library_folder = workspace.read().unwrap().config.library_folder.clone();
+            let play_counts = player.read().unwrap().play_counts.clone();
+            SmartPlaylist::open(path)?.evaluate(library_folder.as_deref(), &play_counts)
+        } else {
+            Saver::restore_playlist(path)
+        }
+    }
+
+    // music files, directories and playlist files given from outside, like command line
+    // arguments, directories are scanned and filtered like with AddAllToQueue
+    pub fn tracks_from_paths(
+        workspace: &Arc<RwLock<Workspace>>,
+        player: &Arc<RwLock<Player>>,
+        paths: &[PathBuf],
+    ) -> Result<VecDeque<PathBuf>> {
+        let filter = workspace.read().unwrap().config.queue_filter.clone();
+        let mut tracks = VecDeque::new();
+
+        for path in paths {
+            let path = path
+                .canonicalize()
+                .map_err(|e| anyhow!("Can't open {}: {}", path.display(), e))?;
+
+            if path.is_dir() {
+                tracks.extend(filter.apply(library::scan(&path), &mut |_, _| {}));
+            } else if library::is_music_file(&path) {
+                tracks.push_back(path);
+            } else if let Some(kind) = PlaylistKind::from_path(&path) {
+                tracks.extend(Self::playlist_tracks(workspace, player, &path, &kind)?);
+            } else {
+                return Err(anyhow!(
+                    "{} is not a music file, directory or playlist",
+                    path.display()
+                ));
+            }
+        }
+
+        Ok(tracks)
+    }
+
+    // playlist list could change since the command was typed
+    fn load_playlist_path(
+        workspace: &Arc<RwLock<Workspace>>,
+        player: &Arc<RwLock<Player>>,
+        path: &Path,
+    ) -> Result<()> {
+        let selected = workspace
+            .read()
+            .unwrap()
+            .tree
+            .playlists
+            .iter()
+            .find(|x| x.path == path)
+            .cloned()
+            .ok_or_else(|| anyhow!("Playlist {} is gone", path.display()))?;
+
+        Self::load_playlist(workspace, player, &selected)
+    }
+
     fn toggle_tree_view(workspace: &Arc<RwLock<Workspace>>, rev: bool) {
         let mut mutex = workspace.write().unwrap();
+        let editor_open = mutex.editor.is_some();
 
         if !rev {
-            mutex.tree.state = mutex.tree.state.next();
+            mutex.tree.state = mutex.tree.state.next(editor_open);
         } else {
-            mutex.tree.state = mutex.tree.state.prev();
+            mutex.tree.state = mutex.tree.state.prev(editor_open);
         }
         mutex.tree.selected = 0;
+        mutex.tree.clear_marks();
     }
 
     fn add_to_queue(workspace: &Arc<RwLock<Workspace>>, player: &Arc<RwLock<Player>>) {
-        let element = {
+        let elements = Self::selected_files(workspace);
+        player.write().unwrap().queue.extend(elements);
+    }
+
+    // music files marked in files view, or the selected one
+    fn selected_files(workspace: &Arc<RwLock<Workspace>>) -> Vec<PathBuf> {
+        let mut mutex = workspace.write().unwrap();
+        if mutex.tree.state != TreeState::Files {
+            return vec![];
+        }
+
+        let elements = mutex
+            .tree
+            .selection(mutex.tree.path_list.len())
+            .into_iter()
+            .map(|index| mutex.tree.path_list[index].clone())
+            .filter(|path| library::is_music_file(path))
+            .collect();
+        mutex.tree.clear_marks();
+
+        elements
+    }
+
+    // selected directory or cwd, recursively and filtered by "queue_filter" from config
+    fn add_all_to_queue(workspace: &Arc<RwLock<Workspace>>, player: &Arc<RwLock<Player>>) {
+        let (root, filter) = {
             let mutex = workspace.read().unwrap();
 
             if mutex.tree.state != TreeState::Files {
                 return;
             }
 
-            let selected = mutex.tree.selected;
-            mutex.tree.path_list[selected].clone()
+            let root = match mutex.tree.path_list.get(mutex.tree.selected) {
+                Some(selected) if selected.is_dir() => selected.clone(),
+                _ => mutex.tree.cwd.clone(),
+            };
+            (root, mutex.config.queue_filter.clone())
         };
 
-        if !element.is_file()
-            || element.extension().is_none()
-            || !MUSIC_EXTENSIONS.contains(&element.extension().unwrap().to_str().unwrap())
+        // big trees take a while, notice is shown only after the first PROGRESS_STEP files
+        let notice = |text: String| workspace.write().unwrap().window = Windows::Notice(text);
+        let paths = library::scan_with_progress(&root, &mut |found| {
+            notice(format!(
+                "Scanning {}\n{} files found",
+                root.display(),
+                found
+            ))
+        });
+        let paths = filter.apply(paths, &mut |checked, total| {
+            notice(format!("Reading durations\n{} of {}", checked, total))
+        });
+
         {
-            return;
+            let mut mutex = workspace.write().unwrap();
+            if let Windows::Notice(_) = mutex.window {
+                mutex.window = Windows::None;
+            }
         }
 
-        player.write().unwrap().queue.push_back(element);
+        player.write().unwrap().queue.extend(paths);
+    }
+
+    // tags are read without holding the lock, queue changed in the meantime is left alone
+    fn sort_queue(workspace: &Arc<RwLock<Workspace>>, player: &Arc<RwLock<Player>>, key: SortKey) {
+        let queue = player.read().unwrap().queue.clone();
+        let order = key.order(&queue);
+
+        let mut mutex = workspace.write().unwrap();
+        let mut player = player.write().unwrap();
+        if player.queue == queue {
+            player.reorder(order);
+            if mutex.tree.state == TreeState::Queue {
+                mutex.tree.clear_marks();
+            }
+        }
     }
 
     fn clear_queue(player: &Arc<RwLock<Player>>) {
@@ -311,32 +778,131 @@ impl Action {
         let tree_state = workspace.read().unwrap().tree.state.clone();
 
         if tree_state == TreeState::Queue {
-            let selected = workspace.read().unwrap().tree.selected;
-
-            if player.read().unwrap().queue.is_empty() {
-                return Ok(());
-            }
+            let queue_len = player.read().unwrap().queue.len();
+            let selection = {
+                let mut mutex = workspace.write().unwrap();
+                let selection = mutex.tree.selection(queue_len);
+                mutex.tree.clear_marks();
+                selection
+            };
 
-            player.write().unwrap().queue.remove(selected);
+            // from the end, so indexes of the rest stay valid
+            let queue_len = {
+                let mut player = player.write().unwrap();
+                for &index in selection.iter().rev() {
+                    player.remove(index);
+                }
+                player.queue.len()
+            };
+            Self::clamp_selected(workspace, queue_len);
         } else if tree_state == TreeState::Playlists {
             let (selected, playlists_dir) = {
-                let mutex = workspace.read().unwrap();
-                let selected = mutex.tree.selected;
-                (
-                    mutex.tree.playlists[selected].clone(),
-                    mutex.config.playlists_folder.clone(),
-                )
+                let mut mutex = workspace.write().unwrap();
+                let selected: Vec<Playlist> = mutex
+                    .tree
+                    .selection(mutex.tree.playlists.len())
+                    .into_iter()
+                    .map(|index| mutex.tree.playlists[index].clone())
+                    .collect();
+                mutex.tree.clear_marks();
+                (selected, mutex.config.playlists_folder.clone())
             };
 
-            fs::remove_file(playlists_dir.join(selected + "." + PLAYLIST_FILE_EXT))?;
+            // kept in trash until exit, so Undo can bring them back
+            let paths: Vec<PathBuf> = selected.into_iter().map(|x| x.path).collect();
+            let result = workspace.write().unwrap().history.delete_playlists(&paths);
             Saver::restore_playlists(Arc::clone(workspace), &playlists_dir)?;
+            result?;
+            let playlists_len = workspace.read().unwrap().tree.playlists.len();
+            Self::clamp_selected(workspace, playlists_len);
+        } else if tree_state == TreeState::Editor {
+            let mut mutex = workspace.write().unwrap();
+            let tracks_len = mutex.editor.as_ref().map(|x| x.tracks.len()).unwrap_or(0);
+            let selection = mutex.tree.selection(tracks_len);
+            mutex.tree.clear_marks();
+
+            if let Some(editor) = mutex.editor.as_mut() {
+                for &index in selection.iter().rev() {
+                    editor.remove(index);
+                }
+                let tracks_len = editor.tracks.len();
+                if mutex.tree.selected >= tracks_len {
+                    mutex.tree.selected = tracks_len.saturating_sub(1);
+                }
+            }
         }
 
         Ok(())
     }
 
-    fn skip(player: &Arc<RwLock<Player>>) {
-        player.write().unwrap().clear();
+    // undo when "undo" is set, redo otherwise
+    fn undo(
+        workspace: &Arc<RwLock<Workspace>>,
+        player: &Arc<RwLock<Player>>,
+        undo: bool,
+    ) -> Result<()> {
+        let (applied, playlists_dir) = {
+            let mut mutex = workspace.write().unwrap();
+            let applied = if undo {
+                mutex.history.undo()?
+            } else {
+                mutex.history.redo()?
+            };
+            (applied, mutex.config.playlists_folder.clone())
+        };
+
+        match applied {
+            Some(Applied::Queue(queue)) => {
+                let queue_len = queue.len();
+                player.write().unwrap().set_queue(queue);
+
+                if workspace.read().unwrap().tree.state == TreeState::Queue {
+                    workspace.write().unwrap().tree.clear_marks();
+                    Self::clamp_selected(workspace, queue_len);
+                }
+            }
+            Some(Applied::Playlist) => {
+                Saver::restore_playlists(Arc::clone(workspace), &playlists_dir)?;
+                if workspace.read().unwrap().tree.state == TreeState::Playlists {
+                    let playlists_len = workspace.read().unwrap().tree.playlists.len();
+                    Self::clamp_selected(workspace, playlists_len);
+                }
+            }
+            None => {}
+        }
+
+        Ok(())
+    }
+
+    // keeps cursor inside the list after rows were removed
+    fn clamp_selected(workspace: &Arc<RwLock<Workspace>>, list_len: usize) {
+        let mut mutex = workspace.write().unwrap();
+        if mutex.tree.selected >= list_len {
+            mutex.tree.selected = list_len.saturating_sub(1);
+        }
+    }
+
+    fn toggle_mark(workspace: &Arc<RwLock<Workspace>>) {
+        let mut mutex = workspace.write().unwrap();
+        let selected = mutex.tree.selected;
+
+        if !mutex.tree.marked.remove(&selected) {
+            mutex.tree.marked.insert(selected);
+        }
+    }
+
+    // leaving visual mode keeps the range marked
+    fn visual_mode(workspace: &Arc<RwLock<Workspace>>) {
+        let mut mutex = workspace.write().unwrap();
+        let selected = mutex.tree.selected;
+
+        match mutex.tree.visual_start.take() {
+            Some(start) => mutex
+                .tree
+                .marked
+                .extend(start.min(selected)..=start.max(selected)),
+            None => mutex.tree.visual_start = Some(selected),
+        }
     }
 
     fn change_volume(player: &Arc<RwLock<Player>>, step: f32, increase: bool) {
@@ -370,7 +936,167 @@ impl Action {
     }
 
     fn escape(workspace: &Arc<RwLock<Workspace>>) {
-        workspace.write().unwrap().window = Windows::None;
+        let mut mutex = workspace.write().unwrap();
+        if mutex.window == Windows::None {
+            mutex.tree.clear_marks();
+        }
+        mutex.window = Windows::None;
+    }
+
+    // bindings of the view it was opened from
+    fn show_help(workspace: &Arc<RwLock<Workspace>>) {
+        let mut mutex = workspace.write().unwrap();
+        mutex.window = Windows::Help(Context::current(&mutex));
+        mutex.stdin_buffer.clear();
+        mutex.popup_selected = 0;
+    }
+
+    // typing filters help, arrows scroll it
+    fn help_input(workspace: &Arc<RwLock<Workspace>>, key_event: KeyEvent) {
+        let mut mutex = workspace.write().unwrap();
+        let Windows::Help(context) = mutex.window else {
+            return;
+        };
+        let last = mutex
+            .config
+            .keymap_local
+            .help(context, &mutex.stdin_buffer)
+            .len()
+            .saturating_sub(1);
+
+        match key_event.code {
+            KeyCode::Esc => {
+                mutex.window = Windows::None;
+                mutex.stdin_buffer.clear();
+            }
+            KeyCode::Up => mutex.popup_selected = mutex.popup_selected.saturating_sub(1),
+            KeyCode::Down => mutex.popup_selected = (mutex.popup_selected + 1).min(last),
+            KeyCode::PageUp => mutex.popup_selected = mutex.popup_selected.saturating_sub(10),
+            KeyCode::PageDown => mutex.popup_selected = (mutex.popup_selected + 10).min(last),
+            KeyCode::Home => mutex.popup_selected = 0,
+            KeyCode::End => mutex.popup_selected = last,
+            _ => {
+                if edit_text(&mut mutex.stdin_buffer, key_event) == TextInput::Edited {
+                    mutex.popup_selected = 0;
+                }
+            }
+        }
+    }
+
+    fn show_command(workspace: &Arc<RwLock<Workspace>>) {
+        let mut mutex = workspace.write().unwrap();
+        mutex.window = Windows::Command;
+        mutex.stdin_buffer.clear();
+        mutex.command.reset();
+    }
+
+    // typing into the command line, Enter gives the action to run
+    fn command_input(
+        workspace: &Arc<RwLock<Workspace>>,
+        key_event: KeyEvent,
+    ) -> Result<Option<(Action, usize)>> {
+        let mut mutex = workspace.write().unwrap();
+        let Workspace {
+            command,
+            stdin_buffer,
+            tree,
+            window,
+            ..
+        } = &mut *mutex;
+
+        match key_event.code {
+            KeyCode::Tab => command.complete(stdin_buffer, tree, true),
+            KeyCode::BackTab => command.complete(stdin_buffer, tree, false),
+            KeyCode::Up => command.browse(stdin_buffer, true),
+            KeyCode::Down => command.browse(stdin_buffer, false),
+            _ => match edit_text(stdin_buffer, key_event) {
+                TextInput::Cancel => {
+                    *window = Windows::None;
+                    stdin_buffer.clear();
+                    command.reset();
+                }
+                TextInput::Submit => {
+                    *window = Windows::None;
+                    let line = std::mem::take(stdin_buffer);
+                    command.push(&line);
+                    return command::parse(&line, tree);
+                }
+                TextInput::Edited => command.stop_completion(),
+                TextInput::Ignored => {}
+            },
+        }
+
+        Ok(None)
+    }
+
+    // a click selects a row or seeks on the progress bar, a second click on the same
+    // row is Select, the wheel scrolls lists and help
+    fn mouse_input(
+        workspace: &Arc<RwLock<Workspace>>,
+        player: &Arc<RwLock<Player>>,
+        mouse_event: MouseEvent,
+        last_click: &mut Option<(Instant, usize)>,
+    ) -> Resolved {
+        let (areas, window) = {
+            let mutex = workspace.read().unwrap();
+            (mutex.areas, mutex.window.clone())
+        };
+        let position = Position::new(mouse_event.column, mouse_event.row);
+
+        match mouse_event.kind {
+            MouseEventKind::ScrollUp | MouseEventKind::ScrollDown => {
+                let up = mouse_event.kind == MouseEventKind::ScrollUp;
+                if let Windows::Help(_) = window {
+                    let code = if up { KeyCode::Up } else { KeyCode::Down };
+                    for _ in 0..SCROLL_LINES {
+                        Self::help_input(workspace, KeyEvent::from(code));
+                    }
+                    return Resolved::Waiting;
+                }
+
+                Resolved::Run(if up { Action::Up } else { Action::Down }, SCROLL_LINES)
+            }
+
+            // dragging keeps seeking while the cursor stays on the bar
+            MouseEventKind::Down(MouseButton::Left) | MouseEventKind::Drag(MouseButton::Left)
+                if window == Windows::None && areas.progress.contains(position) =>
+            {
+                let bar = areas.progress.inner(Margin::new(1, 1));
+                let total = player.read().unwrap().get_duration_total();
+                if bar.width == 0 || total == 0 {
+                    return Resolved::Waiting;
+                }
+
+                let column = position.x.saturating_sub(bar.x).min(bar.width) as usize;
+                Resolved::Run(Action::Seek(total * column / bar.width as usize), 1)
+            }
+
+            MouseEventKind::Down(MouseButton::Left) if window == Windows::None => {
+                let list = areas.list.inner(Margin::new(1, 1));
+                if !list.contains(position) {
+                    return Resolved::Waiting;
+                }
+
+                let row = (position.y - list.y) as usize + areas.list_offset;
+                if row >= Self::list_len(&workspace.read().unwrap(), player) {
+                    return Resolved::Waiting;
+                }
+                workspace.write().unwrap().tree.selected = row;
+
+                let double = last_click.is_some_and(|(time, last_row)| {
+                    last_row == row && time.elapsed() < DOUBLE_CLICK
+                });
+                if double {
+                    *last_click = None;
+                    Resolved::Run(Action::Select, 1)
+                } else {
+                    *last_click = Some((Instant::now(), row));
+                    Resolved::Waiting
+                }
+            }
+
+            _ => Resolved::Waiting,
+        }
     }
 
     fn show_select_theme(workspace: &Arc<RwLock<Workspace>>) {
@@ -408,98 +1134,654 @@ impl Action {
         workspace.write().unwrap().config.selected_theme = selected;
     }
 
-    fn save_playlist(
-        workspace: &Arc<RwLock<Workspace>>,
-        player: &Arc<RwLock<Player>>,
-    ) -> Result<()> {
+    // blocks until Enter or Esc, None when input was cancelled
+    fn read_input(workspace: &Arc<RwLock<Workspace>>, window: Windows) -> Result<Option<String>> {
         workspace.write().unwrap().stdin_buffer.clear();
-        workspace.write().unwrap().window = Windows::PlaylistSave;
+        workspace.write().unwrap().window = window;
 
         loop {
-            if event::poll(Duration::from_millis(100))? {
-                if let event::Event::Key(key_event) = event::read()? {
-                    if let KeyEventKind::Release = key_event.kind {
-                        continue;
-                    }
+            if !workspace.read().unwrap().running {
+                return Ok(None);
+            }
 
-                    if let KeyCode::Char(ch) = key_event.code {
-                        workspace.write().unwrap().stdin_buffer.push(ch);
-                        continue;
-                    }
+            if event::poll(Duration::from_millis(100))?
+                && let event::Event::Key(key_event) = event::read()?
+            {
+                if let KeyEventKind::Release = key_event.kind {
+                    continue;
+                }
 
-                    if let KeyCode::Esc = key_event.code {
-                        let mut mutex = workspace.write().unwrap();
+                let mut mutex = workspace.write().unwrap();
+                match edit_text(&mut mutex.stdin_buffer, key_event) {
+                    TextInput::Cancel => {
                         mutex.window = Windows::None;
                         mutex.stdin_buffer.clear();
-                        return Ok(());
+                        return Ok(None);
                     }
-
-                    if let KeyCode::Backspace = key_event.code {
-                        workspace.write().unwrap().stdin_buffer.pop();
-                        continue;
+                    TextInput::Submit => {
+                        mutex.window = Windows::None;
+                        return Ok(Some(std::mem::take(&mut mutex.stdin_buffer)));
                     }
+                    TextInput::Edited | TextInput::Ignored => {}
+                }
+            }
+        }
+    }
 
-                    if let KeyCode::Enter = key_event.code {
-                        let (save_path, name) = {
-                            let mutex = workspace.read().unwrap();
+    fn save_playlist(
+        workspace: &Arc<RwLock<Workspace>>,
+        player: &Arc<RwLock<Player>>,
+    ) -> Result<()> {
+        // in editor view this saves the playlist being edited
+        if workspace.read().unwrap().tree.state == TreeState::Editor {
+            return Self::save_editor(workspace);
+        }
 
-                            (
-                                mutex.config.playlists_folder.clone(),
-                                mutex.stdin_buffer.clone(),
-                            )
-                        };
+        let Some(input) = Self::read_input(workspace, Windows::PlaylistSave)? else {
+            return Ok(());
+        };
 
-                        let queue = player.read().unwrap().queue.clone();
-                        Saver::save_playlist(&save_path, name.clone(), queue)?;
+        let (save_path, name, format) = {
+            let mutex = workspace.read().unwrap();
+            let (name, format) = PlaylistFormat::split_name(&input, mutex.config.playlist_format);
 
-                        let mut mutex = workspace.write().unwrap();
-                        mutex.stdin_buffer.clear();
-                        mutex.window = Windows::None;
-                        mutex.tree.playlists.push(name);
+            (mutex.config.playlists_folder.clone(), name, format)
+        };
+        Self::check_playlist_name(&name)?;
+
+        let queue = player.read().unwrap().queue.clone();
+        let path = save_path.join(name.clone() + "." + format.extension());
+        workspace
+            .write()
+            .unwrap()
+            .history
+            .write_playlist(&path, || {
+                Saver::save_playlist(&save_path, name, queue, format)
+            })?;
+        Saver::restore_playlists(Arc::clone(workspace), &save_path)?;
 
-                        return Ok(());
-                    }
+        Ok(())
+    }
+
+    fn export_playlist(workspace: &Arc<RwLock<Workspace>>) -> Result<()> {
+        let (selected, playlists_dir) = {
+            let mutex = workspace.read().unwrap();
+            if mutex.tree.state != TreeState::Playlists {
+                return Ok(());
+            }
+
+            let Some(selected) = mutex.tree.playlists.get(mutex.tree.selected) else {
+                return Ok(());
+            };
+            (selected.clone(), mutex.config.playlists_folder.clone())
+        };
+
+        let format = PlaylistFormat::M3u8;
+        let path = selected
+            .path
+            .with_file_name(selected.name.clone() + "." + format.extension());
+        workspace
+            .write()
+            .unwrap()
+            .history
+            .write_playlist(&path, || {
+                Saver::export_playlist(&selected, format).map(|_| ())
+            })?;
+        Saver::restore_playlists(Arc::clone(workspace), &playlists_dir)?;
+
+        Ok(())
+    }
+
+    // playlist under cursor in playlists view or the one open in editor
+    fn current_playlist(workspace: &Arc<RwLock<Workspace>>) -> Option<Playlist> {
+        let mutex = workspace.read().unwrap();
+
+        match mutex.tree.state {
+            TreeState::Playlists => mutex.tree.playlists.get(mutex.tree.selected).cloned(),
+            TreeState::Editor => mutex.editor.as_ref().map(|x| x.playlist.clone()),
+            _ => None,
+        }
+    }
+
+    fn edit_playlist(workspace: &Arc<RwLock<Workspace>>) -> Result<()> {
+        let selected = {
+            let mutex = workspace.read().unwrap();
+            if mutex.tree.state != TreeState::Playlists {
+                return Ok(());
+            }
+
+            let Some(selected) = mutex.tree.playlists.get(mutex.tree.selected) else {
+                return Ok(());
+            };
+            selected.clone()
+        };
+
+        let editor = PlaylistEditor::open(&selected)?;
+        if !Self::discard_edits(workspace)? {
+            return Ok(());
+        }
+
+        let mut mutex = workspace.write().unwrap();
+        mutex.editor = Some(editor);
+        mutex.tree.state = TreeState::Editor;
+        mutex.tree.selected = 0;
+        mutex.tree.clear_marks();
+
+        Ok(())
+    }
+
+    fn close_playlist(workspace: &Arc<RwLock<Workspace>>) -> Result<()> {
+        if !Self::discard_edits(workspace)? {
+            return Ok(());
+        }
+
+        let mut mutex = workspace.write().unwrap();
+        mutex.editor = None;
+
+        if mutex.tree.state == TreeState::Editor {
+            mutex.tree.state = TreeState::Playlists;
+            mutex.tree.selected = 0;
+            mutex.tree.clear_marks();
+        }
+
+        Ok(())
+    }
+
+    // true when the open editor has nothing unsaved or its changes may go
+    fn discard_edits(workspace: &Arc<RwLock<Workspace>>) -> Result<bool> {
+        let name = match &workspace.read().unwrap().editor {
+            Some(editor) if editor.modified => editor.playlist.name.clone(),
+            _ => return Ok(true),
+        };
+
+        Self::confirm(
+            workspace,
+            format!(
+                "\"{}\" has unsaved changes\ny drops them, any other key keeps editing",
+                name
+            ),
+        )
+    }
+
+    // blocks until a key is pressed, only y answers yes
+    fn confirm(workspace: &Arc<RwLock<Workspace>>, question: String) -> Result<bool> {
+        workspace.write().unwrap().window = Windows::Confirm(question);
+
+        loop {
+            if !workspace.read().unwrap().running {
+                return Ok(false);
+            }
+
+            if event::poll(Duration::from_millis(100))?
+                && let event::Event::Key(key_event) = event::read()?
+                && key_event.kind != KeyEventKind::Release
+            {
+                workspace.write().unwrap().window = Windows::None;
+                return Ok(matches!(key_event.code, KeyCode::Char('y' | 'Y')));
+            }
+        }
+    }
+
+    // a playlist name can't point outside the playlists folder or hide the file
+    fn check_playlist_name(name: &str) -> Result<()> {
+        if name.contains(['/', '\\']) || name.starts_with('.') {
+            return Err(anyhow!(
+                "Playlist name can't contain / or \\ or start with a dot"
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn save_editor(workspace: &Arc<RwLock<Workspace>>) -> Result<()> {
+        let playlists_dir = {
+            let mut mutex = workspace.write().unwrap();
+            let Workspace {
+                editor, history, ..
+            } = &mut *mutex;
+            let Some(editor) = editor.as_mut() else {
+                return Ok(());
+            };
+            let path = editor.playlist.path.clone();
+            history.write_playlist(&path, || editor.save())?;
+
+            mutex.config.playlists_folder.clone()
+        };
+
+        Saver::restore_playlists(Arc::clone(workspace), &playlists_dir)
+    }
+
+    fn append_to_playlist(workspace: &Arc<RwLock<Workspace>>) -> Result<()> {
+        if workspace.read().unwrap().editor.is_none() {
+            return Err(anyhow!("Open a playlist with EditPlaylist first"));
+        }
+
+        let elements = Self::selected_files(workspace);
+        if let Some(editor) = workspace.write().unwrap().editor.as_mut() {
+            for element in elements {
+                editor.append(element);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn rename_playlist(workspace: &Arc<RwLock<Workspace>>) -> Result<()> {
+        let Some(playlist) = Self::current_playlist(workspace) else {
+            return Ok(());
+        };
+        let Some(name) = Self::read_input(workspace, Windows::PlaylistRename)? else {
+            return Ok(());
+        };
+        if name.is_empty() {
+            return Ok(());
+        }
+        Self::check_playlist_name(&name)?;
+
+        let extension = playlist
+            .path
+            .extension()
+            .and_then(|x| x.to_str())
+            .unwrap_or(PLAYLIST_FILE_EXT);
+        let new_path = playlist.path.with_file_name(name.clone() + "." + extension);
+        if new_path.exists() {
+            return Err(anyhow!("File {} already exists", new_path.display()));
+        }
+        fs::rename(&playlist.path, &new_path)?;
+
+        let playlists_dir = {
+            let mut mutex = workspace.write().unwrap();
+            if let Some(editor) = mutex.editor.as_mut()
+                && editor.playlist.path == playlist.path
+            {
+                editor.playlist.path = new_path;
+                editor.playlist.name = name;
+            }
+
+            mutex.config.playlists_folder.clone()
+        };
+
+        Saver::restore_playlists(Arc::clone(workspace), &playlists_dir)
+    }
+
+    fn duplicate_playlist(workspace: &Arc<RwLock<Workspace>>) -> Result<()> {
+        let Some(playlist) = Self::current_playlist(workspace) else {
+            return Ok(());
+        };
+        let Some(name) = Self::read_input(workspace, Windows::PlaylistDuplicate)? else {
+            return Ok(());
+        };
+        if name.is_empty() {
+            return Ok(());
+        }
+        Self::check_playlist_name(&name)?;
+
+        let playlists_dir = {
+            let mut mutex = workspace.write().unwrap();
+
+            // in editor the copy gets current edits and the editor switches to it
+            if mutex.tree.state == TreeState::Editor
+                && let Some(editor) = mutex.editor.as_mut()
+            {
+                editor.save_as(name)?;
+            } else {
+                let extension = playlist
+                    .path
+                    .extension()
+                    .and_then(|x| x.to_str())
+                    .unwrap_or(PLAYLIST_FILE_EXT);
+                let new_path = playlist.path.with_file_name(name + "." + extension);
+                if new_path.exists() {
+                    return Err(anyhow!("File {} already exists", new_path.display()));
                 }
+                fs::copy(&playlist.path, new_path)?;
             }
+
+            mutex.config.playlists_folder.clone()
+        };
+
+        Saver::restore_playlists(Arc::clone(workspace), &playlists_dir)
+    }
+
+    // selection follows the moved track
+    fn move_track(workspace: &Arc<RwLock<Workspace>>, player: &Arc<RwLock<Player>>, up: bool) {
+        let mut mutex = workspace.write().unwrap();
+        let selected = mutex.tree.selected;
+        mutex.tree.clear_marks();
+
+        if mutex.tree.state == TreeState::Editor
+            && let Some(editor) = mutex.editor.as_mut()
+        {
+            mutex.tree.selected = editor.move_track(selected, up);
+        } else if mutex.tree.state == TreeState::Queue {
+            mutex.tree.selected = player.write().unwrap().move_track(selected, up);
         }
     }
+
+    fn move_to_top(workspace: &Arc<RwLock<Workspace>>, player: &Arc<RwLock<Player>>) {
+        let mut mutex = workspace.write().unwrap();
+        let selected = mutex.tree.selected;
+        mutex.tree.clear_marks();
+
+        if mutex.tree.state == TreeState::Editor
+            && let Some(editor) = mutex.editor.as_mut()
+        {
+            editor.move_to_top(selected);
+            mutex.tree.selected = 0;
+        } else if mutex.tree.state == TreeState::Queue {
+            player.write().unwrap().move_to_top(selected);
+            mutex.tree.selected = 0;
+        }
+    }
+
+    fn play_next(workspace: &Arc<RwLock<Workspace>>, player: &Arc<RwLock<Player>>) {
+        let elements = Self::selected_files(workspace);
+        player.write().unwrap().play_next(elements);
+    }
+
+    // relinks tracks of the selected playlist, the open editor or the queue
+    // and shows what is still missing
+    fn relink(
+        workspace: &Arc<RwLock<Workspace>>,
+        player: &Arc<RwLock<Player>>,
+        relink: impl Fn(&mut [PlaylistTrack]) -> usize,
+    ) -> Result<()> {
+        let tree_state = workspace.read().unwrap().tree.state.clone();
+
+        let (name, tracks, relinked) = match tree_state {
+            TreeState::Queue => {
+                let queue = player.read().unwrap().queue.clone();
+                let mut tracks: Vec<PlaylistTrack> =
+                    queue.into_iter().map(PlaylistTrack::new).collect();
+                let relinked = relink(&mut tracks);
+
+                let mut mutex = player.write().unwrap();
+                if relinked != 0 && mutex.queue.len() == tracks.len() {
+                    let after: VecDeque<PathBuf> =
+                        tracks.iter().map(|track| track.path.clone()).collect();
+                    let before = std::mem::replace(&mut mutex.queue, after.clone());
+                    drop(mutex);
+                    workspace
+                        .write()
+                        .unwrap()
+                        .history
+                        .push(Change::Queue { before, after });
+                }
+
+                ("Queue".to_string(), tracks, relinked)
+            }
+            TreeState::Playlists => {
+                let Some(playlist) = Self::current_playlist(workspace) else {
+                    return Ok(());
+                };
+                if playlist.kind == PlaylistKind::Smart {
+                    return Err(anyhow!("Smart playlists are built from the library"));
+                }
+
+                let mut tracks = Saver::restore_playlist_tracks(&playlist.path)?;
+                let relinked = relink(&mut tracks);
+                if relinked != 0 {
+                    let format =
+                        PlaylistFormat::from_path(&playlist.path).unwrap_or(PlaylistFormat::Plist);
+                    workspace
+                        .write()
+                        .unwrap()
+                        .history
+                        .write_playlist(&playlist.path, || {
+                            Saver::write_playlist(&playlist.path, tracks.clone(), format)
+                        })?;
+                }
+
+                (playlist.name, tracks, relinked)
+            }
+            TreeState::Editor => {
+                let mut mutex = workspace.write().unwrap();
+                let Some(editor) = mutex.editor.as_mut() else {
+                    return Ok(());
+                };
+
+                let relinked = relink(&mut editor.tracks);
+                if relinked != 0 {
+                    editor.modified = true;
+                }
+
+                (
+                    editor.playlist.name.clone(),
+                    editor.tracks.clone(),
+                    relinked,
+                )
+            }
+            TreeState::Files => return Ok(()),
+        };
+
+        let mut lines = vec![];
+        if relinked != 0 {
+            lines.push(format!("Relinked {} tracks", relinked));
+        }
+        lines.extend(
+            tracks
+                .iter()
+                .filter(|track| !track.path.exists())
+                .map(|track| track.path.display().to_string()),
+        );
+        if lines.is_empty() {
+            lines.push("No missing tracks".to_string());
+        }
+
+        let mut mutex = workspace.write().unwrap();
+        mutex.popup_selected = 0;
+        mutex.window = Windows::Report(format!("Missing tracks: {}", name), lines);
+
+        Ok(())
+    }
+
+    // input is "/old/prefix -> /new/prefix"
+    fn relink_prefix(
+        workspace: &Arc<RwLock<Workspace>>,
+        player: &Arc<RwLock<Player>>,
+    ) -> Result<()> {
+        if workspace.read().unwrap().tree.state == TreeState::Files {
+            return Ok(());
+        }
+        let Some(input) = Self::read_input(workspace, Windows::RelinkPrefix)? else {
+            return Ok(());
+        };
+
+        let Some((from, to)) = input.split_once("->").or_else(|| input.split_once("=>")) else {
+            return Err(anyhow!("Expected \"old prefix -> new prefix\""));
+        };
+        let (from, to) = (PathBuf::from(from.trim()), PathBuf::from(to.trim()));
+
+        Self::relink(workspace, player, |tracks| {
+            library::relink_prefix(tracks, &from, &to)
+        })
+    }
+
+    fn relink_search(
+        workspace: &Arc<RwLock<Workspace>>,
+        player: &Arc<RwLock<Player>>,
+    ) -> Result<()> {
+        let library_folder = workspace
+            .read()
+            .unwrap()
+            .config
+            .library_folder
+            .clone()
+            .ok_or_else(|| anyhow!("Set \"library_folder\" in preferences"))?;
+
+        Self::relink(workspace, player, |tracks| {
+            library::relink_search(tracks, &library_folder)
+        })
+    }
+
+    // new keymaps, themes and playlists folder take effect right away
+    pub fn reload_config(workspace: &Arc<RwLock<Workspace>>) -> Result<()> {
+        let config = workspace.read().unwrap().config.clone();
+        let (new_config, warnings) = config.reload()?;
+
+        let folder_changed = new_config.playlists_folder != config.playlists_folder;
+        let playlists_warning = if folder_changed {
+            new_config.prepare_playlists_folder()?
+        } else {
+            None
+        };
+        let playlists_folder = new_config.playlists_folder.clone();
+        workspace.write().unwrap().config = new_config;
+
+        // playlists of the old folder are gone from the list either way
+        if folder_changed && playlists_folder.exists() {
+            Saver::restore_playlists(Arc::clone(workspace), &playlists_folder)?;
+        } else if folder_changed {
+            workspace.write().unwrap().tree.playlists.clear();
+        }
+
+        let mut mutex = workspace.write().unwrap();
+        if let Some(warning) = playlists_warning {
+            mutex.window = Windows::Error(warning);
+        } else if !warnings.is_empty() && mutex.window == Windows::None {
+            mutex.window = Windows::Error(format!(
+                "Config reloaded with {} warning(s), run `musicshell --check-config` to see them",
+                warnings.len()
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 pub fn main_loop(
     workspace: Arc<RwLock<Workspace>>,
     player: Arc<RwLock<Player>>,
-    save_file: &PathBuf,
+    save_file: &Path,
 ) -> Result<()> {
-    let config = workspace.read().unwrap().config.clone();
+    let mut key_state = KeyState::default();
+    let mut last_click = None;
 
     loop {
-        if event::poll(Duration::from_millis(100))? {
-            if let event::Event::Key(key_event) = event::read()? {
-                if let Some(action) = config.keymap_local.get(&key_event) {
-                    if *action == Action::Exit {
-                        Saver::default().save(
-                            Arc::clone(&player),
-                            Arc::clone(&workspace),
-                            save_file,
-                        )?;
-
-                        workspace.write().unwrap().running = false;
-                        break;
-                    }
+        // stopped from outside, by a signal
+        if !workspace.read().unwrap().running {
+            break;
+        }
 
-                    if let Err(err) =
-                        action.perform_action(Arc::clone(&workspace), Arc::clone(&player))
-                    {
-                        workspace.write().unwrap().window = Windows::Error(err.to_string());
+        // keymap is looked up every time, it changes with ReloadConfig
+        let resolved = if event::poll(Duration::from_millis(100))? {
+            match event::read()? {
+                event::Event::Key(key_event)
+                    if key_event.kind != KeyEventKind::Release
+                        && matches!(workspace.read().unwrap().window, Windows::Help(_)) =>
+                {
+                    Action::help_input(&workspace, key_event);
+                    Resolved::Waiting
+                }
+                event::Event::Key(key_event)
+                    if key_event.kind != KeyEventKind::Release
+                        && workspace.read().unwrap().window == Windows::Command =>
+                {
+                    let command =
+                        Action::command_input(&workspace, key_event).unwrap_or_else(|err| {
+                            workspace.write().unwrap().window = Windows::Error(err.to_string());
+                            None
+                        });
+                    match command {
+                        Some((action, count)) => Resolved::Run(action, count),
+                        None => Resolved::Waiting,
                     }
                 }
+                event::Event::Mouse(mouse_event) => {
+                    Action::mouse_input(&workspace, &player, mouse_event, &mut last_click)
+                }
+                event::Event::Key(key_event) if key_event.kind != KeyEventKind::Release => {
+                    let mutex = workspace.read().unwrap();
+                    let context = Context::current(&mutex);
+                    key_state.press(&mutex.config.keymap_local, context, key_event)
+                }
+                _ => Resolved::Waiting,
             }
+        } else {
+            let mutex = workspace.read().unwrap();
+            let context = Context::current(&mutex);
+            key_state.timeout(
+                &mutex.config.keymap_local,
+                context,
+                mutex.config.key_timeout,
+            )
+        };
+
+        let pending_keys = key_state.display();
+        if workspace.read().unwrap().pending_keys != pending_keys {
+            workspace.write().unwrap().pending_keys = pending_keys;
+        }
+
+        let Resolved::Run(action, count) = resolved else {
+            continue;
+        };
+
+        if action == Action::Exit {
+            exit(&workspace, &player, save_file)?;
+            break;
+        }
+
+        if let Err(err) = action.perform_action(Arc::clone(&workspace), Arc::clone(&player), count)
+        {
+            workspace.write().unwrap().window = Windows::Error(err.to_string());
+        }
+
+        let mut mutex = workspace.write().unwrap();
+        if let Some(warning) = mutex.command.warning.take()
+            && mutex.window == Windows::None
+        {
+            mutex.window = Windows::Error(warning);
         }
     }
 
     Ok(())
 }
 
+// state is saved before running is cleared, every thread stops after that
+pub fn exit(
+    workspace: &Arc<RwLock<Workspace>>,
+    player: &Arc<RwLock<Player>>,
+    save_file: &Path,
+) -> Result<()> {
+    let result = Saver::default().save(Arc::clone(player), Arc::clone(workspace), save_file);
+
+    let mut mutex = workspace.write().unwrap();
+    mutex.history.clear_trash();
+    mutex.running = false;
+
+    result
+}
+
+// Text input ==============================================
+#[derive(PartialEq)]
+enum TextInput {
+    Edited,
+    Submit,
+    Cancel,
+    Ignored,
+}
+
+// typing, Backspace, Enter and Esc, shared by every prompt
+fn edit_text(buffer: &mut String, key_event: KeyEvent) -> TextInput {
+    match key_event.code {
+        KeyCode::Char(ch)
+            if !key_event
+                .modifiers
+                .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+        {
+            buffer.push(ch);
+            TextInput::Edited
+        }
+        KeyCode::Backspace => {
+            buffer.pop();
+            TextInput::Edited
+        }
+        KeyCode::Enter => TextInput::Submit,
+        KeyCode::Esc => TextInput::Cancel,
+        _ => TextInput::Ignored,
+    }
+}
+
 // Sorting implementation
 trait PathList {
     fn sort_paths(&mut self);
@@ -0,0 +1,111 @@
+use std::{env, path::PathBuf};
+
+use anyhow::{anyhow, Result};
+
+pub const USAGE: &str = "\
+Usage: musicshell [OPTIONS] [PATHS...]
+       musicshell ctl <COMMAND>
+
+Plays the given music files, directories and playlist files instead of the saved queue.
+When musicshell is already running they are sent to it and played after the current track
+
+Options:
+  --config <FILE>     config file to use
+  --state <FILE>      state file to use
+  --check-config      check the config and exit
+  --no-restore        don't restore the saved queue, position and view
+  --start-dir <DIR>   directory shown in Files view
+  --shuffle           shuffle the tracks from PATHS
+  --enqueue           add PATHS to the end of the queue instead of playing them
+  --volume <0-100>    start with this volume
+  -h, --help          print this help
+  -V, --version       print version
+
+`musicshell ctl --help` lists commands for a running player";
+
+// Command line ============================================
+#[derive(Debug, Default)]
+pub struct Args {
+    pub config: Option<PathBuf>,
+    pub state: Option<PathBuf>,
+    // only validate the config and exit
+    pub check_config: bool,
+    pub no_restore: bool,
+    pub start_dir: Option<PathBuf>,
+    pub shuffle: bool,
+    // PATHS go to the end of the queue
+    pub enqueue: bool,
+    // percent
+    pub volume: Option<usize>,
+    pub help: bool,
+    pub version: bool,
+    // files, directories and playlists to play
+    pub paths: Vec<PathBuf>,
+    // arguments after "ctl", sent to the running instance
+    pub ctl: Option<Vec<String>>,
+}
+
+impl Args {
+    pub fn parse() -> Result<Self> {
+        Self::parse_from(env::args().skip(1))
+    }
+
+    // both "--config path" and "--config=path" work, everything after "--" is a path
+    pub fn parse_from(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut result = Args::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--check-config" => result.check_config = true,
+                "--no-restore" => result.no_restore = true,
+                "--shuffle" => result.shuffle = true,
+                "--enqueue" => result.enqueue = true,
+                "-h" | "--help" => result.help = true,
+                "-V" | "--version" => result.version = true,
+                "ctl" if result.paths.is_empty() => {
+                    result.ctl = Some(args.by_ref().collect());
+                    break;
+                }
+                "--" => {
+                    result.paths.extend(args.by_ref().map(PathBuf::from));
+                    break;
+                }
+                _ if !arg.starts_with('-') => result.paths.push(PathBuf::from(arg)),
+                _ => result.parse_option(arg, &mut args)?,
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn parse_option(&mut self, arg: String, args: &mut impl Iterator<Item = String>) -> Result<()> {
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => {
+                (name.to_string(), Some(value.to_string()))
+            }
+            _ => (arg, None),
+        };
+        let value = inline_value
+            .or_else(|| args.next())
+            .ok_or_else(|| anyhow!("{} expects a value", name));
+
+        match name.as_str() {
+            "--config" => self.config = Some(PathBuf::from(value?)),
+            "--state" => self.state = Some(PathBuf::from(value?)),
+            "--start-dir" => self.start_dir = Some(PathBuf::from(value?)),
+            "--volume" => {
+                let value = value?;
+                let volume = value
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|x| *x <= 100)
+                    .ok_or_else(|| anyhow!("--volume expects 0 to 100, got \"{}\"", value))?;
+                self.volume = Some(volume);
+            }
+            _ => return Err(anyhow!("Unknown argument \"{}\", see --help", name)),
+        }
+
+        Ok(())
+    }
+}
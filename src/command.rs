@@ -0,0 +1,495 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    actions::{self, Action},
+    keymap::COUNT_LIMIT,
+    paths,
+    validate::suggest,
+    workspace::Tree,
+};
+
+// oldest commands are forgotten after this
+const HISTORY_LIMIT: usize = 100;
+
+// commands that aren't actions
+pub const COMMAND_NAMES: [&str; 2] = ["playlist", "cd"];
+
+// Command line ============================================
+// candidate for Tab, the whole line it gives and the word shown in the prompt
+pub struct Completion {
+    pub line: String,
+    pub label: String,
+}
+
+#[derive(Default)]
+pub struct CommandLine {
+    history: Vec<String>,
+    file: Option<PathBuf>,
+    // position in history while browsing it, the typed line is kept aside
+    browsing: Option<usize>,
+    draft: String,
+    pub completions: Vec<Completion>,
+    // shown candidate, None until Tab is pressed again with several of them
+    pub completion: Option<usize>,
+    // history file that couldn't be written, shown after the command ran
+    pub warning: Option<String>,
+}
+
+impl CommandLine {
+    // missing or unreadable history just starts empty
+    pub fn load(file: PathBuf) -> Self {
+        let history = fs::read_to_string(&file)
+            .map(|content| {
+                content
+                    .lines()
+                    .filter(|line| !line.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        CommandLine {
+            history,
+            file: Some(file),
+            ..Default::default()
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.browsing = None;
+        self.draft.clear();
+        self.stop_completion();
+    }
+
+    pub fn stop_completion(&mut self) {
+        self.completions.clear();
+        self.completion = None;
+    }
+
+    // history is written right away, so it's there even after a crash,
+    // a full or read-only disk doesn't stop the command from running
+    pub fn push(&mut self, line: &str) {
+        self.reset();
+
+        let line = line.trim();
+        if line.is_empty() || self.history.last().is_some_and(|last| last == line) {
+            return;
+        }
+        self.history.push(line.to_string());
+        if self.history.len() > HISTORY_LIMIT {
+            self.history.drain(..self.history.len() - HISTORY_LIMIT);
+        }
+
+        if let Some(file) = &self.file
+            && let Err(e) = fs::write(file, self.history.join("\n") + "\n")
+        {
+            self.warning = Some(format!("Command history isn't saved: {}", e));
+        }
+    }
+
+    // Up goes to older commands, Down back to newer ones and then to what was typed
+    pub fn browse(&mut self, line: &mut String, older: bool) {
+        self.stop_completion();
+
+        match (self.browsing, older) {
+            (None, true) if !self.history.is_empty() => {
+                self.draft = std::mem::take(line);
+                self.browsing = Some(self.history.len() - 1);
+            }
+            (Some(index), true) => self.browsing = Some(index.saturating_sub(1)),
+            (Some(index), false) if index + 1 < self.history.len() => {
+                self.browsing = Some(index + 1)
+            }
+            (Some(_), false) => {
+                self.browsing = None;
+                *line = std::mem::take(&mut self.draft);
+                return;
+            }
+            _ => return,
+        }
+
+        if let Some(index) = self.browsing {
+            *line = self.history[index].clone();
+        }
+    }
+
+    // Tab completes the last word, with several candidates it shows them
+    // and the next Tab (or BackTab) cycles through
+    pub fn complete(&mut self, line: &mut String, tree: &Tree, forward: bool) {
+        self.browsing = None;
+
+        let index = match self.completion {
+            Some(index) if forward => (index + 1) % self.completions.len(),
+            Some(index) => index.checked_sub(1).unwrap_or(self.completions.len() - 1),
+            None => {
+                self.completions = completions(line, tree);
+                match self.completions.len() {
+                    0 => return,
+                    1 => {
+                        *line = self.completions.remove(0).line;
+                        return;
+                    }
+                    _ if forward => 0,
+                    len => len - 1,
+                }
+            }
+        };
+
+        self.completion = Some(index);
+        *line = self.completions[index].line.clone();
+    }
+}
+
+// Completion ==============================================
+fn completions(line: &str, tree: &Tree) -> Vec<Completion> {
+    let line = line.trim_start();
+
+    let Some((name, rest)) = line.split_once(' ') else {
+        let mut names: Vec<&str> = actions::action_names()
+            .into_iter()
+            .chain(actions::arg_action_names())
+            .filter(|name| starts_with_ignore_case(name, line))
+            .collect();
+        names.sort();
+
+        // commands go first, they need one more word
+        return COMMAND_NAMES
+            .iter()
+            .filter(|name| starts_with_ignore_case(name, line))
+            .map(|name| completion(format!("{} ", name), name))
+            .chain(
+                names
+                    .into_iter()
+                    .map(|name| completion(name.to_string(), name)),
+            )
+            .collect();
+    };
+
+    match name.to_lowercase().as_str() {
+        "playlist" => match rest.split_once(' ') {
+            None if starts_with_ignore_case("load", rest) => {
+                vec![completion("playlist load ".to_string(), "load")]
+            }
+            Some(("load", typed)) => tree
+                .playlists
+                .iter()
+                .filter(|playlist| starts_with_ignore_case(&playlist.name, typed))
+                .map(|playlist| {
+                    completion(format!("playlist load {}", playlist.name), &playlist.name)
+                })
+                .collect(),
+            _ => vec![],
+        },
+        "cd" => dir_completions(rest, &tree.cwd)
+            .into_iter()
+            .map(|(path, label)| completion(format!("{} {}", name, path), &label))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+// subdirectories starting with what is typed after the last slash,
+// "~/Mu" gives ("~/Music/", "Music/"), hidden ones only when typed with a dot
+fn dir_completions(typed: &str, cwd: &Path) -> Vec<(String, String)> {
+    let (parent, prefix) = match typed.rfind(['/', '\\']) {
+        Some(index) => typed.split_at(index + 1),
+        None => ("", typed),
+    };
+    let Ok(dir) = paths::expand_home(parent) else {
+        return vec![];
+    };
+    let Ok(entries) = cwd.join(dir).read_dir() else {
+        return vec![];
+    };
+
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().to_str().map(String::from))
+        .filter(|name| {
+            name.starts_with(prefix) && (prefix.starts_with('.') || !name.starts_with('.'))
+        })
+        .collect();
+    names.sort();
+
+    names
+        .into_iter()
+        .map(|name| (format!("{}{}/", parent, name), format!("{}/", name)))
+        .collect()
+}
+
+fn completion(line: String, label: &str) -> Completion {
+    Completion {
+        line,
+        label: label.to_string(),
+    }
+}
+
+fn starts_with_ignore_case(text: &str, prefix: &str) -> bool {
+    text.to_lowercase().starts_with(&prefix.to_lowercase())
+}
+
+// Parsing =================================================
+// action to run and how many times, None for an empty line
+pub fn parse(line: &str, tree: &Tree) -> Result<Option<(Action, usize)>> {
+    let line = line.trim();
+    let (name, rest) = match line.split_once(char::is_whitespace) {
+        Some((name, rest)) => (name, rest.trim()),
+        None => (line, ""),
+    };
+    if name.is_empty() {
+        return Ok(None);
+    }
+
+    let action = match name.to_lowercase().as_str() {
+        "seek" => Action::Seek(
+            parse_time(rest).ok_or_else(|| anyhow!("`seek` needs a time like `83` or `1:23`"))?,
+        ),
+        "playlist" => parse_playlist(rest, tree)?,
+        "cd" => {
+            let dir = paths::expand_home(if rest.is_empty() { "~" } else { rest })?;
+            let dir = tree
+                .cwd
+                .join(dir)
+                .canonicalize()
+                .map_err(|e| anyhow!("Can't open {}: {}", rest, e))?;
+            if !dir.is_dir() {
+                return Err(anyhow!("{} is not a directory", dir.display()));
+            }
+            Action::ChangeDir(dir)
+        }
+        _ => return parse_action(name, rest).map(Some),
+    };
+
+    Ok(Some((action, 1)))
+}
+
+// `VolumeIncrease 10` sets the argument, `Down 5` is a count like `5j`
+fn parse_action(name: &str, rest: &str) -> Result<(Action, usize)> {
+    let number = match rest {
+        "" => None,
+        _ => Some(
+            rest.parse::<usize>()
+                .map_err(|_| anyhow!("`{}` is not a number", rest))?,
+        ),
+    };
+
+    if let Some(arg_name) = actions::arg_action_names()
+        .into_iter()
+        .find(|arg_name| arg_name.eq_ignore_ascii_case(name))
+    {
+        let action = number
+            .and_then(|arg| Action::from_str_arg(arg_name, arg))
+            .ok_or_else(|| anyhow!("`{}` needs a number", arg_name))?;
+        return Ok((action, 1));
+    }
+
+    if let Some(action) = actions::action_names()
+        .into_iter()
+        .find(|action_name| action_name.eq_ignore_ascii_case(name))
+        .and_then(Action::from_str)
+    {
+        return Ok((action, number.unwrap_or(1).clamp(1, COUNT_LIMIT)));
+    }
+
+    let names: Vec<&str> = actions::action_names()
+        .into_iter()
+        .chain(actions::arg_action_names())
+        .chain(COMMAND_NAMES)
+        .collect();
+    Err(anyhow!(
+        "Unknown command `{}`{}",
+        name,
+        suggest(name, &names)
+    ))
+}
+
+// `playlist load NAME`
+fn parse_playlist(rest: &str, tree: &Tree) -> Result<Action> {
+    let Some(("load", name)) = rest.split_once(' ').map(|(word, name)| (word, name.trim())) else {
+        return Err(anyhow!("Use `playlist load NAME`"));
+    };
+
+    find_playlist(name, tree).map(Action::LoadPlaylist)
+}
+
+// name is matched exactly first and then ignoring case
+pub fn find_playlist(name: &str, tree: &Tree) -> Result<PathBuf> {
+    tree.playlists
+        .iter()
+        .find(|playlist| playlist.name == name)
+        .or_else(|| {
+            tree.playlists
+                .iter()
+                .find(|playlist| playlist.name.eq_ignore_ascii_case(name))
+        })
+        .map(|playlist| playlist.path.clone())
+        .ok_or_else(|| {
+            let names: Vec<&str> = tree.playlists.iter().map(|x| x.name.as_str()).collect();
+            anyhow!("No playlist named `{}`{}", name, suggest(name, &names))
+        })
+}
+
+// "83", "1:23" or "1:02:03" in seconds
+pub fn parse_time(text: &str) -> Option<usize> {
+    let parts: Vec<&str> = text.split(':').collect();
+    if parts.len() > 3 {
+        return None;
+    }
+
+    parts
+        .iter()
+        .enumerate()
+        .try_fold(0, |total, (index, part)| {
+            let value: usize = part.parse().ok()?;
+            if index != 0 && value >= 60 {
+                return None;
+            }
+            Some(total * 60 + value)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workspace::{Playlist, PlaylistKind};
+
+    // tree in an empty temp folder with a "Music" subfolder and two playlists
+    fn tree(name: &str) -> Tree {
+        let cwd =
+            std::env::temp_dir().join(format!("musicshell-test-{}-{}", std::process::id(), name));
+        fs::create_dir_all(cwd.join("Music")).unwrap();
+        let mut tree = Tree::new(cwd.canonicalize().unwrap()).unwrap();
+        tree.playlists = ["Road trip", "road"]
+            .into_iter()
+            .map(|name| Playlist {
+                name: name.to_string(),
+                path: PathBuf::from(format!("/playlists/{}.plist", name)),
+                kind: PlaylistKind::Native,
+                info: None,
+            })
+            .collect();
+        tree
+    }
+
+    fn parsed(line: &str, tree: &Tree) -> Option<(Action, usize)> {
+        parse(line, tree).unwrap()
+    }
+
+    fn error(line: &str, tree: &Tree) -> String {
+        parse(line, tree).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn parse_time_formats() {
+        assert_eq!(parse_time("83"), Some(83));
+        assert_eq!(parse_time("1:23"), Some(83));
+        assert_eq!(parse_time("1:02:03"), Some(3723));
+        assert_eq!(parse_time("120:00"), Some(7200));
+        assert_eq!(parse_time("1:60"), None);
+        assert_eq!(parse_time("1:2:3:4"), None);
+        assert_eq!(parse_time("1:"), None);
+        assert_eq!(parse_time("-1"), None);
+        assert_eq!(parse_time(""), None);
+    }
+
+    #[test]
+    fn parse_actions() {
+        let tree = tree("actions");
+        assert_eq!(parsed("  ", &tree), None);
+        assert_eq!(parsed("Down", &tree), Some((Action::Down, 1)));
+        assert_eq!(parsed("down 5", &tree), Some((Action::Down, 5)));
+        assert_eq!(parsed("Down 0", &tree), Some((Action::Down, 1)));
+        assert_eq!(
+            parsed("Down 5000", &tree),
+            Some((Action::Down, COUNT_LIMIT))
+        );
+        assert_eq!(
+            parsed("volumeincrease 10", &tree),
+            Some((Action::VolumeIncrease(10), 1))
+        );
+        assert_eq!(parsed("seek 1:23", &tree), Some((Action::Seek(83), 1)));
+
+        assert_eq!(
+            error("VolumeIncrease", &tree),
+            "`VolumeIncrease` needs a number"
+        );
+        assert_eq!(error("Down five", &tree), "`five` is not a number");
+        assert_eq!(
+            error("seek 1:99", &tree),
+            "`seek` needs a time like `83` or `1:23`"
+        );
+        assert_eq!(
+            error("Dwon", &tree),
+            "Unknown command `Dwon` (did you mean `Down`?)"
+        );
+    }
+
+    #[test]
+    fn parse_playlist_load() {
+        let tree = tree("playlists");
+        assert_eq!(
+            parsed("playlist load road", &tree),
+            Some((
+                Action::LoadPlaylist(PathBuf::from("/playlists/road.plist")),
+                1
+            ))
+        );
+        assert_eq!(
+            parsed("playlist load ROAD TRIP", &tree),
+            Some((
+                Action::LoadPlaylist(PathBuf::from("/playlists/Road trip.plist")),
+                1
+            ))
+        );
+        assert!(error("playlist load rood", &tree).starts_with("No playlist named `rood`"));
+        assert_eq!(error("playlist road", &tree), "Use `playlist load NAME`");
+    }
+
+    #[test]
+    fn parse_cd() {
+        let tree = tree("cd");
+        assert_eq!(
+            parsed("cd Music", &tree),
+            Some((Action::ChangeDir(tree.cwd.join("Music")), 1))
+        );
+        assert_eq!(
+            parsed("cd Music/..", &tree),
+            Some((Action::ChangeDir(tree.cwd.clone()), 1))
+        );
+        assert!(error("cd Videos", &tree).starts_with("Can't open Videos"));
+    }
+
+    #[test]
+    fn browse_history() {
+        let mut command = CommandLine::default();
+        command.push("Down 2");
+        command.push("Down 2");
+        command.push(" Up ");
+        assert_eq!(command.history, ["Down 2", "Up"]);
+
+        let mut line = String::from("typed");
+        command.browse(&mut line, true);
+        assert_eq!(line, "Up");
+        command.browse(&mut line, true);
+        command.browse(&mut line, true);
+        assert_eq!(line, "Down 2");
+        command.browse(&mut line, false);
+        command.browse(&mut line, false);
+        assert_eq!(line, "typed");
+    }
+
+    #[test]
+    fn unwritable_history_is_a_warning() {
+        let tree = tree("history");
+        let mut command = CommandLine::load(tree.cwd.join("Music"));
+        command.push("Down");
+        assert_eq!(command.history, ["Down"]);
+        assert!(command.warning.is_some());
+    }
+}
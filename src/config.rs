@@ -1,33 +1,108 @@
 #![allow(dead_code, unused_variables)]
-use std::{collections::HashMap, fs, path::PathBuf, str::FromStr};
+use std::{collections::HashMap, fs, path::PathBuf, time::Duration};
 
-use crate::actions::*;
+use crate::{
+    actions::*,
+    formats::PlaylistFormat,
+    keymap::{parse_keys, parse_modifier, Context, Keymap},
+    library::QueueFilter,
+    paths,
+    validate::{self, Diagnostic, Severity},
+};
 use anyhow::{anyhow, Result};
-use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use serde::Deserialize;
 use toml::Value;
 
 #[derive(Clone)]
 pub struct Config {
-    pub keymap_local: HashMap<KeyEvent, Action>,
+    pub keymap_local: Keymap,
     pub themes: HashMap<String, Theme>,
     pub selected_theme: String,
     pub playlists_folder: PathBuf,
+    pub library_folder: Option<PathBuf>,
+    pub playlist_format: PlaylistFormat,
+    pub queue_filter: QueueFilter,
+    pub auto_resume: bool,
+    pub autosave_interval: u64,
+    pub watch_config: bool,
+    // how long a key sequence like "gg" waits for the next key
+    pub key_timeout: Duration,
+    // file it was read from, used by ReloadConfig
+    pub file: PathBuf,
 }
 
 impl Config {
     pub fn new(parser: Parser) -> Result<Self> {
         let keymap_local = parser.parse_keys()?;
         let selected_theme = parser.parse_selected_theme()?;
-        let playlists_folder = parser.parse_playlists_folder()?;
+        let playlists_folder = match parser.parse_playlists_folder()? {
+            Some(playlists_folder) => playlists_folder,
+            None => paths::data_dir()?.join("playlists"),
+        };
+        let library_folder = parser.parse_library_folder()?;
+        let playlist_format = parser.parse_playlist_format()?;
+        let queue_filter = parser.parse_queue_filter()?;
+        let auto_resume = parser.parse_auto_resume()?;
+        let autosave_interval = parser.parse_autosave_interval()?;
+        let watch_config = parser.parse_watch_config()?;
+        let key_timeout = parser.parse_key_timeout()?;
         let themes = parser.parse_themes()?;
         Ok(Config {
             keymap_local,
             themes,
             selected_theme,
             playlists_folder,
+            library_folder,
+            playlist_format,
+            queue_filter,
+            auto_resume,
+            autosave_interval,
+            watch_config,
+            key_timeout,
+            file: parser.file,
         })
     }
+
+    // the default folder is made when missing, a configured one is left to the user,
+    // the warning is shown instead
+    pub fn prepare_playlists_folder(&self) -> Result<Option<String>> {
+        if self.playlists_folder.exists() {
+            return Ok(None);
+        }
+        if self.playlists_folder == paths::data_dir()?.join("playlists") {
+            fs::create_dir_all(&self.playlists_folder)?;
+            return Ok(None);
+        }
+
+        Ok(Some(format!(
+            "Playlists folder doesn't exist:\n{}",
+            self.playlists_folder.display()
+        )))
+    }
+
+    // config read again from the same file, an error leaves the running one untouched
+    // selected theme is kept when it still exists, it could be changed with SelectTheme
+    pub fn reload(&self) -> Result<(Config, Vec<Diagnostic>)> {
+        let content = fs::read_to_string(&self.file)?;
+        let (errors, warnings): (Vec<Diagnostic>, Vec<Diagnostic>) =
+            validate::check(&self.file, &content)
+                .into_iter()
+                .partition(|x| x.severity == Severity::Error);
+        if !errors.is_empty() {
+            let errors: Vec<String> = errors.iter().map(|x| x.to_string()).collect();
+            return Err(anyhow!(
+                "Config not reloaded, the old one stays:\n{}",
+                errors.join("\n")
+            ));
+        }
+
+        let mut config = Config::new(Parser::new(self.file.clone())?)?;
+        if config.themes.contains_key(&self.selected_theme) {
+            config.selected_theme = self.selected_theme.clone();
+        }
+
+        Ok((config, warnings))
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -43,10 +118,14 @@ pub struct Theme {
     pub directories: [u8; 3],
     pub error: [u8; 3],
     pub error_text: [u8; 3],
+    // rows selected with ToggleMark or VisualMode, progress_bar_elapsed when not set
+    #[serde(default)]
+    pub marked: Option<[u8; 3]>,
 }
 
 pub struct Parser {
     config: Value,
+    file: PathBuf,
 }
 
 #[derive(Debug, Deserialize)]
@@ -61,79 +140,58 @@ struct KeymapEntry {
 
 impl Parser {
     pub fn new(file: PathBuf) -> Result<Self> {
-        let content = fs::read_to_string(file)?;
+        let content = fs::read_to_string(&file)?;
         let config = toml::de::from_str(&content)?;
-        Ok(Parser { config })
+        Ok(Parser { config, file })
     }
 
-    pub fn parse_keys(&self) -> Result<HashMap<KeyEvent, Action>> {
+    pub fn parse_keys(&self) -> Result<Keymap> {
         let std_error = "Config file isn't structured as expected";
 
         let keys_list = self.config.get("keymaps");
         let keys_list = keys_list.ok_or_else(|| anyhow!(std_error))?;
 
+        // sections named after a context only work there, the rest are global
         let mut keymap_entries = vec![];
-        for (_, vars) in keys_list.as_table().unwrap() {
-            if let Some(keymap) = vars.get("keymap") {
-                if let Some(keymap_array) = keymap.as_array() {
-                    for entry in keymap_array {
-                        let entry_struct: KeymapEntry =
-                            toml::de::from_str(&entry.as_table().unwrap().to_string())?;
-                        keymap_entries.push(entry_struct);
-                    }
+        for (name, vars) in keys_list.as_table().unwrap() {
+            let context = Context::from_str(name).unwrap_or(Context::Global);
+            if let Some(keymap) = vars.get("keymap")
+                && let Some(keymap_array) = keymap.as_array()
+            {
+                for entry in keymap_array {
+                    let entry_struct: KeymapEntry =
+                        toml::de::from_str(&entry.as_table().unwrap().to_string())?;
+                    keymap_entries.push((context, name, entry_struct));
                 }
             }
         }
 
-        let result = keymap_entries
-            .into_iter()
-            .map(|entry| {
-                let action;
-                if let Some(arg) = entry.arg {
-                    action = Action::from_str_arg(&entry.action, arg).unwrap_or(Action::None);
-                } else {
-                    action = Action::from_str(&entry.action).unwrap_or(Action::None);
-                }
+        let mut result = Keymap::default();
+        for (context, section, entry) in keymap_entries {
+            let action;
+            if let Some(arg) = entry.arg {
+                action = Action::from_str_arg(&entry.action, arg).unwrap_or(Action::None);
+            } else {
+                action = Action::from_str(&entry.action).unwrap_or(Action::None);
+            }
+
+            // unknown keys are reported by validate
+            let Some(mut keys) = parse_keys(&entry.key) else {
+                continue;
+            };
 
-                let key_code = match entry.key.as_str() {
-                    "SPACE" => KeyCode::Char(' '),
-                    "BACKSPACE" => KeyCode::Backspace,
-                    "TAB" => KeyCode::Tab,
-                    "BACKTAB" => KeyCode::BackTab,
-                    "DEL" => KeyCode::Delete,
-                    "ENTER" => KeyCode::Enter,
-                    "ESC" => KeyCode::Esc,
-                    "ARROW_UP" => KeyCode::Up,
-                    "ARROW_DOWN" => KeyCode::Down,
-                    "ARROW_LEFT" => KeyCode::Left,
-                    "ARROW_RIGHT" => KeyCode::Right,
-                    k if k.starts_with('F') => k[1..]
-                        .parse::<u8>()
-                        .ok()
-                        .filter(|&n| (1..=12).contains(&n))
-                        .map(KeyCode::F)
-                        .unwrap_or(KeyCode::Null),
-                    k if k.len() == 1 => KeyCode::Char(k.chars().next().unwrap()),
-                    _ => KeyCode::Null,
-                };
-
-                let mut key_modifiers = KeyModifiers::empty();
-                if let Some(mods) = entry.mods {
-                    for modificator in mods {
-                        match modificator.as_str() {
-                            "SHIFT" => key_modifiers.insert(KeyModifiers::SHIFT),
-                            "SUPER" => key_modifiers.insert(KeyModifiers::SUPER),
-                            "ALT" => key_modifiers.insert(KeyModifiers::ALT),
-                            "CTRL" => key_modifiers.insert(KeyModifiers::CONTROL),
-                            _ => {}
-                        }
+            // "mods" apply to every key of a sequence, on top of "C-" like prefixes
+            if let Some(mods) = entry.mods {
+                for modificator in mods {
+                    if let Some(modifier) = parse_modifier(&modificator) {
+                        keys.iter_mut()
+                            .for_each(|key| key.modifiers.insert(modifier));
                     }
                 }
+            }
 
-                let key_event = KeyEvent::new(key_code, key_modifiers);
-                (key_event, action)
-            })
-            .collect();
+            result.insert(context, section, keys, action);
+        }
 
         Ok(result)
     }
@@ -152,34 +210,101 @@ impl Parser {
     }
 
     pub fn parse_selected_theme(&self) -> Result<String> {
-        let table = self
-            .config
-            .get("preferences")
-            .ok_or_else(|| anyhow!("Expected \"preferences\" table to be in config file"))?
-            .as_table()
-            .ok_or_else(|| anyhow!("Expected \"preferences\" to be a table"))?;
-        let selected_theme = table
-            .get("selected_theme")
-            .ok_or_else(|| anyhow!("Expected \"selected_theme\" to be in preferences table"))?
-            .as_str()
-            .ok_or_else(|| anyhow!("Expected \"selected_theme\" to be a string"))?;
+        self.preference_str("selected_theme")?
+            .map(String::from)
+            .ok_or_else(|| anyhow!("Expected \"selected_theme\" to be in preferences table"))
+    }
+
+    // optional, "playlists" in the data directory when missing
+    pub fn parse_playlists_folder(&self) -> Result<Option<PathBuf>> {
+        Ok(self.preference_str("playlists_folder")?.map(PathBuf::from))
+    }
 
-        Ok(selected_theme.to_string())
+    // optional, used by smart playlists
+    pub fn parse_library_folder(&self) -> Result<Option<PathBuf>> {
+        Ok(self.preference_str("library_folder")?.map(PathBuf::from))
     }
 
-    pub fn parse_playlists_folder(&self) -> Result<PathBuf> {
+    // optional, "plist" when missing
+    pub fn parse_playlist_format(&self) -> Result<PlaylistFormat> {
+        let Some(playlist_format) = self.preference_str("playlist_format")? else {
+            return Ok(PlaylistFormat::Plist);
+        };
+        PlaylistFormat::from_str(playlist_format)
+            .ok_or_else(|| anyhow!("Unknown playlist format \"{}\"", playlist_format))
+    }
+
+    // optional, AddAllToQueue takes everything when missing
+    pub fn parse_queue_filter(&self) -> Result<QueueFilter> {
+        let Some(queue_filter) = self.config.get("queue_filter") else {
+            return Ok(QueueFilter::default());
+        };
+
+        queue_filter
+            .clone()
+            .try_into()
+            .map_err(|e| anyhow!("Error occured while parsing \"queue_filter\":\n{e}"))
+    }
+
+    // optional, playback continues after restart unless it is false
+    pub fn parse_auto_resume(&self) -> Result<bool> {
+        Ok(self.preference_bool("auto_resume")?.unwrap_or(true))
+    }
+
+    pub fn parse_watch_config(&self) -> Result<bool> {
+        Ok(self.preference_bool("watch_config")?.unwrap_or(false))
+    }
+
+    // optional, seconds between autosaves, 0 turns autosave off
+    pub fn parse_autosave_interval(&self) -> Result<u64> {
+        Ok(self
+            .preference_number("autosave_interval", "seconds")?
+            .unwrap_or(30))
+    }
+
+    pub fn parse_key_timeout(&self) -> Result<Duration> {
+        let key_timeout = self.preference_number("key_timeout", "milliseconds")?;
+        Ok(Duration::from_millis(key_timeout.unwrap_or(1000)))
+    }
+
+    // Preferences =========================================
+    // None when the key is missing, an error when it has the wrong type
+    fn preference(&self, name: &str) -> Result<Option<&Value>> {
         let table = self
             .config
             .get("preferences")
             .ok_or_else(|| anyhow!("Expected \"preferences\" table to be in config file"))?
             .as_table()
             .ok_or_else(|| anyhow!("Expected \"preferences\" to be a table"))?;
-        let selected_theme = table
-            .get("playlists_folder")
-            .ok_or_else(|| anyhow!("Expected \"playlists_folder\" to be in preferences table"))?
-            .as_str()
-            .ok_or_else(|| anyhow!("Expected \"playlists_folder\" to be a string"))?;
 
-        PathBuf::from_str(selected_theme).map_err(|e| anyhow!(e))
+        Ok(table.get(name))
+    }
+
+    fn preference_str(&self, name: &str) -> Result<Option<&str>> {
+        self.preference(name)?
+            .map(|x| {
+                x.as_str()
+                    .ok_or_else(|| anyhow!("Expected \"{}\" to be a string", name))
+            })
+            .transpose()
+    }
+
+    fn preference_bool(&self, name: &str) -> Result<Option<bool>> {
+        self.preference(name)?
+            .map(|x| {
+                x.as_bool()
+                    .ok_or_else(|| anyhow!("Expected \"{}\" to be true or false", name))
+            })
+            .transpose()
+    }
+
+    fn preference_number(&self, name: &str, unit: &str) -> Result<Option<u64>> {
+        self.preference(name)?
+            .map(|x| {
+                x.as_integer()
+                    .and_then(|x| u64::try_from(x).ok())
+                    .ok_or_else(|| anyhow!("Expected \"{}\" to be a number of {}", name, unit))
+            })
+            .transpose()
     }
 }
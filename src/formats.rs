@@ -0,0 +1,833 @@
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+use quick_xml::{escape::escape, events::Event, Reader};
+use serde::{Deserialize, Serialize};
+use toml::{
+    value::{Date, Datetime, Offset, Time},
+    Table,
+};
+
+use crate::library::TrackInfo;
+
+pub const M3U_FILE_EXT: &str = "m3u";
+pub const M3U8_FILE_EXT: &str = "m3u8";
+pub const XSPF_FILE_EXT: &str = "xspf";
+pub const PLS_FILE_EXT: &str = "pls";
+
+// every file musicshell writes itself starts with this header
+pub const FORMAT_VERSION: i64 = 2;
+pub const STATE_FORMAT: &str = "musicshell-state";
+pub const PLAYLIST_FORMAT: &str = "musicshell-playlist";
+
+// Playlist formats ========================================
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PlaylistFormat {
+    Plist,
+    M3u8,
+    Xspf,
+    Pls,
+}
+
+impl PlaylistFormat {
+    pub fn from_str(format_str: &str) -> Option<Self> {
+        match format_str.to_lowercase().as_str() {
+            "plist" => Some(PlaylistFormat::Plist),
+            "m3u" | "m3u8" => Some(PlaylistFormat::M3u8),
+            "xspf" => Some(PlaylistFormat::Xspf),
+            "pls" => Some(PlaylistFormat::Pls),
+            _ => None,
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()
+            .and_then(|x| x.to_str())
+            .and_then(Self::from_str)
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            PlaylistFormat::Plist => crate::workspace::PLAYLIST_FILE_EXT,
+            PlaylistFormat::M3u8 => M3U8_FILE_EXT,
+            PlaylistFormat::Xspf => XSPF_FILE_EXT,
+            PlaylistFormat::Pls => PLS_FILE_EXT,
+        }
+    }
+
+    // plist keeps only paths and sizes, the others have title and duration per track
+    pub fn stores_tags(&self) -> bool {
+        *self != PlaylistFormat::Plist
+    }
+
+    // "Road trip.m3u8" picks the format by itself, plain names use the default one
+    pub fn split_name(name: &str, default: Self) -> (String, Self) {
+        if let Some((stem, ext)) = name.rsplit_once('.')
+            && let Some(format) = Self::from_str(ext)
+            && !stem.is_empty()
+        {
+            return (stem.to_string(), format);
+        }
+
+        (name.to_string(), default)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlaylistTrack {
+    pub path: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<usize>,
+    // file size helps to find the track again after it was moved
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+impl PlaylistTrack {
+    pub fn new(path: PathBuf) -> Self {
+        PlaylistTrack {
+            path,
+            title: None,
+            duration: None,
+            size: None,
+        }
+    }
+
+    pub fn with_size(mut self) -> Self {
+        if self.size.is_none() {
+            self.size = self.path.metadata().ok().map(|x| x.len());
+        }
+
+        self
+    }
+
+    // fills title, duration and size from the file when they aren't known yet
+    pub fn with_info(self) -> Self {
+        let mut track = self.with_size();
+        if track.title.is_some() && track.duration.is_some() {
+            return track;
+        }
+
+        let info = TrackInfo::read(&track.path);
+        if track.title.is_none() {
+            track.title = Some(info.display_title(&track.path));
+        }
+        if track.duration.is_none() {
+            track.duration = info.duration;
+        }
+
+        track
+    }
+}
+
+// paths in playlists are relative to the playlist file, "file://" URIs are decoded,
+// other URLs (streams) are kept as they are and show up as missing tracks
+fn resolve(base: &Path, entry: &str) -> PathBuf {
+    let path = decode_uri(entry).unwrap_or_else(|| PathBuf::from(entry));
+    if path.is_absolute() || is_url(entry) {
+        path
+    } else {
+        base.join(path)
+    }
+}
+
+// "http://...", but not "C:/..." or a file name with a colon in it
+fn is_url(entry: &str) -> bool {
+    entry.split_once("://").is_some_and(|(scheme, _)| {
+        scheme.len() > 1
+            && scheme
+                .chars()
+                .all(|x| x.is_ascii_alphanumeric() || "+.-".contains(x))
+    })
+}
+
+// file:///home/me/Music/Song%20name.mp3 -> /home/me/Music/Song name.mp3
+pub fn decode_uri(uri: &str) -> Option<PathBuf> {
+    let rest = uri.strip_prefix("file://")?;
+    let rest = rest.strip_prefix("localhost").unwrap_or(rest);
+    let decoded = percent_decode(rest)?;
+
+    // file:///C:/Music on windows
+    if cfg!(target_os = "windows") && decoded.get(2..3) == Some(":") {
+        return Some(PathBuf::from(&decoded[1..]));
+    }
+
+    Some(PathBuf::from(decoded))
+}
+
+// Song%20name.mp3 -> Song name.mp3
+fn percent_decode(text: &str) -> Option<String> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(byte) = text
+                .get(i + 1..i + 3)
+                .and_then(|x| u8::from_str_radix(x, 16).ok())
+        {
+            decoded.push(byte);
+            i += 3;
+            continue;
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+// xspf locations are URIs, relative ones are percent-encoded too
+fn resolve_uri(base: &Path, location: &str) -> PathBuf {
+    if location.starts_with("file://") || is_url(location) {
+        return resolve(base, location);
+    }
+    let decoded = percent_decode(location).unwrap_or_else(|| location.to_string());
+    resolve(base, &decoded)
+}
+
+pub fn encode_uri(path: &Path) -> Option<String> {
+    let path_str = path.to_str()?.replace('\\', "/");
+    let mut result = String::from("file://");
+    if !path_str.starts_with('/') {
+        result.push('/');
+    }
+
+    for byte in path_str.bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~:".contains(&byte) {
+            result.push(byte as char);
+        } else {
+            result += &format!("%{:02X}", byte);
+        }
+    }
+
+    Some(result)
+}
+
+fn relativize(base: &Path, path: &Path) -> PathBuf {
+    path.strip_prefix(base)
+        .map(|x| x.to_path_buf())
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+// Versioned files =========================================
+pub fn header(format: &str) -> String {
+    format!(
+        "# Written by musicshell, keep the header if you edit this file\nformat = \"{}\"\nversion = {}\n\n",
+        format, FORMAT_VERSION
+    )
+}
+
+// None means the file is not in versioned format yet (bincode from older versions),
+// a text file with a "format" line is ours and its syntax errors are reported
+pub fn read_versioned(content: &[u8], format: &str) -> Result<Option<Table>> {
+    let Ok(text) = std::str::from_utf8(content) else {
+        return Ok(None);
+    };
+    let table = match text.parse::<Table>() {
+        Ok(table) => table,
+        Err(e) if text.lines().any(|x| x.trim_start().starts_with("format")) => {
+            return Err(anyhow!("Error occured while parsing file:\n{e}"));
+        }
+        Err(_) => return Ok(None),
+    };
+    if table.get("format").and_then(|x| x.as_str()) != Some(format) {
+        return Ok(None);
+    }
+
+    let version = table
+        .get("version")
+        .and_then(|x| x.as_integer())
+        .ok_or_else(|| anyhow!("File header has no version"))?;
+    if version > FORMAT_VERSION {
+        return Err(anyhow!(
+            "File was written by a newer musicshell (format version {})",
+            version
+        ));
+    }
+
+    Ok(Some(table))
+}
+
+// temp file in the same folder and rename over the target,
+// so a crash in the middle never leaves a half written file
+pub fn write_atomic(path: &Path, content: &[u8]) -> Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+
+    // synced before rename, so a crash leaves either the old file or the new one
+    File::create(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(content)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&temp_path, path))
+        .map_err(|e| {
+            let _ = fs::remove_file(&temp_path);
+            anyhow!("Can't write {}:\n{}", path.display(), e)
+        })
+}
+
+// copy made before anything overwrites a file we can't read with the current format,
+// older backups are kept: .bak, .bak.1, .bak.2, ...
+pub fn backup(path: &Path) -> Result<PathBuf> {
+    let backup_path = (0..)
+        .map(|i| {
+            let mut backup_path = path.as_os_str().to_owned();
+            backup_path.push(".bak");
+            if i != 0 {
+                backup_path.push(format!(".{}", i));
+            }
+            PathBuf::from(backup_path)
+        })
+        .find(|x| !x.exists())
+        .unwrap();
+
+    fs::copy(path, &backup_path)
+        .map_err(|e| anyhow!("Can't write backup {}:\n{}", backup_path.display(), e))?;
+
+    Ok(backup_path)
+}
+
+// unreadable file is renamed, so the next save doesn't overwrite it
+pub fn move_aside(path: &Path) -> Result<PathBuf> {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0);
+    let mut new_path = path.as_os_str().to_owned();
+    new_path.push(format!(".corrupt-{}", secs));
+    let new_path = PathBuf::from(new_path);
+
+    fs::rename(path, &new_path)
+        .map_err(|e| anyhow!("Can't move {} aside:\n{}", path.display(), e))?;
+
+    Ok(new_path)
+}
+
+pub fn datetime(time: SystemTime) -> Datetime {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0) as i64;
+    let (days, secs) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+
+    // days since epoch to civil date, http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    Datetime {
+        date: Some(Date {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+        }),
+        time: Some(Time {
+            hour: (secs / 3600) as u8,
+            minute: (secs / 60 % 60) as u8,
+            second: (secs % 60) as u8,
+            nanosecond: 0,
+        }),
+        offset: Some(Offset::Z),
+    }
+}
+
+// Native playlists ========================================
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaylistFile {
+    #[serde(default)]
+    pub description: String,
+    pub created: Datetime,
+    pub modified: Datetime,
+    #[serde(default, rename = "track")]
+    pub tracks: Vec<PlaylistTrack>,
+}
+
+impl PlaylistFile {
+    pub fn new(tracks: Vec<PlaylistTrack>) -> Self {
+        let now = datetime(SystemTime::now());
+
+        PlaylistFile {
+            description: String::new(),
+            created: now,
+            modified: now,
+            tracks,
+        }
+    }
+}
+
+// old bincode playlists are read as they are, write_plist migrates them
+pub fn parse_plist(path: &Path) -> Result<PlaylistFile> {
+    let content = fs::read(path)?;
+    // dates don't survive a detour through Table, the text is parsed again
+    if read_versioned(&content, PLAYLIST_FORMAT)?.is_some() {
+        return toml::from_str(&String::from_utf8_lossy(&content))
+            .map_err(|e| anyhow!("Error occured while parsing file:\n{e}"));
+    }
+
+    let queue: VecDeque<PathBuf> = bincode::deserialize(&content)
+        .map_err(|e| anyhow!("Error occured while parsing file:\n{e}"))?;
+    let mut playlist = PlaylistFile::new(queue.into_iter().map(PlaylistTrack::new).collect());
+    if let Ok(modified) = path.metadata().and_then(|x| x.modified()) {
+        playlist.created = datetime(modified);
+        playlist.modified = datetime(modified);
+    }
+
+    Ok(playlist)
+}
+
+// an old or broken file is kept as .bak before it is overwritten
+pub fn write_plist(path: &Path, playlist: &PlaylistFile) -> Result<()> {
+    if let Ok(content) = fs::read(path)
+        && !matches!(read_versioned(&content, PLAYLIST_FORMAT), Ok(Some(_)))
+    {
+        backup(path)?;
+    }
+
+    let content = header(PLAYLIST_FORMAT) + &toml::to_string(playlist)?;
+    write_atomic(path, content.as_bytes())
+}
+
+// M3U =====================================================
+pub fn parse_m3u(path: &Path) -> Result<Vec<PlaylistTrack>> {
+    let content = fs::read(path)?;
+    let content = String::from_utf8_lossy(&content);
+    let base = path.parent().unwrap_or(Path::new(""));
+
+    let mut result = vec![];
+    let mut extinf: Option<(Option<usize>, Option<String>)> = None;
+
+    for line in content.lines() {
+        let line = line.trim_start_matches('\u{feff}').trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        // #EXTINF:123,Artist - Title
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            let (duration, title) = info.split_once(',').unwrap_or((info, ""));
+            let duration = duration
+                .split_whitespace()
+                .next()
+                .and_then(|x| x.parse::<i64>().ok())
+                .filter(|x| *x >= 0)
+                .map(|x| x as usize);
+            let title = Some(title.trim().to_string()).filter(|x| !x.is_empty());
+            extinf = Some((duration, title));
+            continue;
+        }
+        if line.starts_with('#') {
+            continue;
+        }
+
+        let (duration, title) = extinf.take().unwrap_or((None, None));
+        result.push(PlaylistTrack {
+            path: resolve(base, line),
+            title,
+            duration,
+            size: None,
+        });
+    }
+
+    Ok(result)
+}
+
+pub fn write_m3u(path: &Path, tracks: &[PlaylistTrack]) -> Result<()> {
+    let base = path.parent().unwrap_or(Path::new(""));
+
+    let mut content = String::from("#EXTM3U\n");
+    for track in tracks {
+        let path_str = relativize(base, &track.path);
+        let path_str = path_str
+            .to_str()
+            .ok_or_else(|| anyhow!("Path {} is not valid UTF-8", track.path.display()))?;
+
+        content += &format!(
+            "#EXTINF:{},{}\n{}\n",
+            track.duration.map(|x| x as i64).unwrap_or(-1),
+            track.title.clone().unwrap_or_default(),
+            path_str
+        );
+    }
+
+    write_atomic(path, content.as_bytes())
+}
+
+// XSPF ====================================================
+pub fn parse_xspf(path: &Path) -> Result<Vec<PlaylistTrack>> {
+    let content = fs::read_to_string(path)?;
+    let base = path.parent().unwrap_or(Path::new(""));
+
+    let mut reader = Reader::from_str(&content);
+    reader.config_mut().trim_text(true);
+
+    let mut result = vec![];
+    let mut track: Option<(Option<String>, Option<String>, Option<usize>)> = None;
+    let mut element = String::new();
+
+    loop {
+        match reader
+            .read_event()
+            .map_err(|e| anyhow!("Error occured while parsing file:\n{e}"))?
+        {
+            Event::Start(tag) => {
+                element = String::from_utf8_lossy(tag.local_name().as_ref()).to_string();
+                if element == "track" {
+                    track = Some((None, None, None));
+                }
+            }
+            Event::Text(text) => {
+                let text = text
+                    .unescape()
+                    .map_err(|e| anyhow!("Error occured while parsing file:\n{e}"))?;
+                set_xspf_field(&mut track, &element, text.to_string());
+            }
+            // <location><![CDATA[...]]></location>
+            Event::CData(data) => {
+                let text = String::from_utf8_lossy(&data).trim().to_string();
+                set_xspf_field(&mut track, &element, text);
+            }
+            Event::End(tag) => {
+                if tag.local_name().as_ref() == b"track"
+                    && let Some((Some(location), title, duration)) = track.take()
+                {
+                    result.push(PlaylistTrack {
+                        path: resolve_uri(base, &location),
+                        title,
+                        duration,
+                        size: None,
+                    });
+                }
+                element.clear();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(result)
+}
+
+fn set_xspf_field(
+    track: &mut Option<(Option<String>, Option<String>, Option<usize>)>,
+    element: &str,
+    text: String,
+) {
+    let Some((location, title, duration)) = track.as_mut() else {
+        return;
+    };
+
+    match element {
+        // only the first location is used
+        "location" if location.is_none() => *location = Some(text),
+        "title" => *title = Some(text),
+        // milliseconds in xspf
+        "duration" => *duration = text.parse::<usize>().ok().map(|x| x / 1000),
+        _ => {}
+    }
+}
+
+pub fn write_xspf(path: &Path, tracks: &[PlaylistTrack]) -> Result<()> {
+    let mut content = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n  <trackList>\n",
+    );
+
+    for track in tracks {
+        let location = encode_uri(&track.path)
+            .ok_or_else(|| anyhow!("Path {} is not valid UTF-8", track.path.display()))?;
+
+        content += "    <track>\n";
+        content += &format!("      <location>{}</location>\n", escape(location));
+        if let Some(title) = &track.title {
+            content += &format!("      <title>{}</title>\n", escape(title.as_str()));
+        }
+        if let Some(duration) = track.duration {
+            content += &format!("      <duration>{}</duration>\n", duration * 1000);
+        }
+        content += "    </track>\n";
+    }
+    content += "  </trackList>\n</playlist>\n";
+
+    write_atomic(path, content.as_bytes())
+}
+
+// PLS =====================================================
+pub fn parse_pls(path: &Path) -> Result<Vec<PlaylistTrack>> {
+    let content = fs::read(path)?;
+    let content = String::from_utf8_lossy(&content);
+    let base = path.parent().unwrap_or(Path::new(""));
+
+    // File1=..., Title1=..., Length1=...
+    let mut entries: Vec<(usize, PlaylistTrack)> = vec![];
+    let mut titles: Vec<(usize, String)> = vec![];
+    let mut lengths: Vec<(usize, usize)> = vec![];
+
+    for line in content.lines() {
+        let Some((key, value)) = line.trim().split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_lowercase();
+        let value = value.trim();
+
+        if let Some(index) = key.strip_prefix("file").and_then(|x| x.parse().ok()) {
+            entries.push((index, PlaylistTrack::new(resolve(base, value))));
+        } else if let Some(index) = key.strip_prefix("title").and_then(|x| x.parse().ok()) {
+            titles.push((index, value.to_string()));
+        } else if let Some(index) = key.strip_prefix("length").and_then(|x| x.parse().ok())
+            && let Ok(length) = value.parse::<i64>()
+            && length >= 0
+        {
+            lengths.push((index, length as usize));
+        }
+    }
+
+    entries.sort_by_key(|(index, _)| *index);
+    let result = entries
+        .into_iter()
+        .map(|(index, mut track)| {
+            track.title = titles
+                .iter()
+                .find(|(i, _)| *i == index)
+                .map(|(_, x)| x.clone());
+            track.duration = lengths.iter().find(|(i, _)| *i == index).map(|(_, x)| *x);
+            track
+        })
+        .collect();
+
+    Ok(result)
+}
+
+pub fn write_pls(path: &Path, tracks: &[PlaylistTrack]) -> Result<()> {
+    let base = path.parent().unwrap_or(Path::new(""));
+
+    let mut content = String::from("[playlist]\n");
+    for (i, track) in tracks.iter().enumerate() {
+        let path_str = relativize(base, &track.path);
+        let path_str = path_str
+            .to_str()
+            .ok_or_else(|| anyhow!("Path {} is not valid UTF-8", track.path.display()))?;
+
+        content += &format!("File{}={}\n", i + 1, path_str);
+        if let Some(title) = &track.title {
+            content += &format!("Title{}={}\n", i + 1, title);
+        }
+        content += &format!(
+            "Length{}={}\n",
+            i + 1,
+            track.duration.map(|x| x as i64).unwrap_or(-1)
+        );
+    }
+    content += &format!("NumberOfEntries={}\nVersion=2\n", tracks.len());
+
+    write_atomic(path, content.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a playlist file in its own temp folder, relative entries resolve against it
+    fn playlist(name: &str, content: &str) -> PathBuf {
+        let folder = std::env::temp_dir().join(format!("musicshell-test-{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let path = folder.join(name);
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn parse_m3u_with_extinf() {
+        let path = playlist(
+            "extinf.m3u",
+            "\u{feff}#EXTM3U\n#EXTINF:123,Artist - Title\nSongs/one.mp3\n\n# comment\n#EXTINF:-1,\n/music/two.mp3\nhttp://radio.example/stream\n",
+        );
+        let base = path.parent().unwrap();
+
+        let tracks = parse_m3u(&path).unwrap();
+        assert_eq!(tracks.len(), 3);
+        assert_eq!(tracks[0].path, base.join("Songs/one.mp3"));
+        assert_eq!(tracks[0].title.as_deref(), Some("Artist - Title"));
+        assert_eq!(tracks[0].duration, Some(123));
+        assert_eq!(tracks[1].path, PathBuf::from("/music/two.mp3"));
+        assert_eq!(tracks[1].title, None);
+        assert_eq!(tracks[1].duration, None);
+        assert_eq!(tracks[2].path, PathBuf::from("http://radio.example/stream"));
+    }
+
+    #[test]
+    fn parse_m3u_file_uris() {
+        let path = playlist(
+            "uris.m3u",
+            "file:///music/Song%20name.mp3\nfile://localhost/music/caf%C3%A9.mp3\n",
+        );
+
+        let tracks = parse_m3u(&path).unwrap();
+        assert_eq!(tracks[0].path, PathBuf::from("/music/Song name.mp3"));
+        assert_eq!(tracks[1].path, PathBuf::from("/music/café.mp3"));
+    }
+
+    #[test]
+    fn percent_decode_keeps_invalid_escapes() {
+        assert_eq!(percent_decode("a%20b").as_deref(), Some("a b"));
+        assert_eq!(percent_decode("100%").as_deref(), Some("100%"));
+        assert_eq!(percent_decode("%zz%4").as_deref(), Some("%zz%4"));
+        assert_eq!(percent_decode("%FF"), None);
+    }
+
+    #[test]
+    fn decode_uri_needs_file_scheme() {
+        assert_eq!(
+            decode_uri("file:///a/b.mp3"),
+            Some(PathBuf::from("/a/b.mp3"))
+        );
+        assert_eq!(decode_uri("/a/b.mp3"), None);
+        assert_eq!(decode_uri("http://example.com/b.mp3"), None);
+    }
+
+    #[test]
+    fn encode_uri_round_trip() {
+        let path = Path::new("/music/Artist & Co/Song #1 (café).mp3");
+        let uri = encode_uri(path).unwrap();
+        assert_eq!(
+            uri,
+            "file:///music/Artist%20%26%20Co/Song%20%231%20%28caf%C3%A9%29.mp3"
+        );
+        assert_eq!(decode_uri(&uri).as_deref(), Some(path));
+    }
+
+    #[test]
+    fn parse_xspf_locations() {
+        let path = playlist(
+            "locations.xspf",
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<playlist version="1" xmlns="http://xspf.org/ns/0/">
+  <trackList>
+    <track>
+      <location>file:///music/Song%20one.mp3</location>
+      <location>file:///music/ignored.mp3</location>
+      <title>One &amp; only</title>
+      <duration>61500</duration>
+    </track>
+    <track>
+      <location>Songs/Song%20two.mp3</location>
+    </track>
+    <track>
+      <location><![CDATA[ /music/three & more.mp3 ]]></location>
+    </track>
+    <track>
+      <title>No location</title>
+    </track>
+  </trackList>
+</playlist>
+"#,
+        );
+        let base = path.parent().unwrap();
+
+        let tracks = parse_xspf(&path).unwrap();
+        assert_eq!(tracks.len(), 3);
+        assert_eq!(tracks[0].path, PathBuf::from("/music/Song one.mp3"));
+        assert_eq!(tracks[0].title.as_deref(), Some("One & only"));
+        assert_eq!(tracks[0].duration, Some(61));
+        assert_eq!(tracks[1].path, base.join("Songs/Song two.mp3"));
+        assert_eq!(tracks[1].title, None);
+        assert_eq!(tracks[2].path, PathBuf::from("/music/three & more.mp3"));
+    }
+
+    #[test]
+    fn parse_xspf_reports_broken_xml() {
+        let path = playlist(
+            "broken.xspf",
+            "<playlist><trackList><track><location>a.mp3</track></playlist>",
+        );
+        assert!(parse_xspf(&path).is_err());
+    }
+
+    #[test]
+    fn parse_pls_entries_by_index() {
+        let path = playlist(
+            "entries.pls",
+            "[playlist]\nFile2=/music/two.mp3\nTitle2=Two\nLength2=-1\nfile1 = one.mp3\nTITLE1=One\nLength1=200\nNumberOfEntries=2\nVersion=2\n",
+        );
+        let base = path.parent().unwrap();
+
+        let tracks = parse_pls(&path).unwrap();
+        assert_eq!(tracks.len(), 2);
+        assert_eq!(tracks[0].path, base.join("one.mp3"));
+        assert_eq!(tracks[0].title.as_deref(), Some("One"));
+        assert_eq!(tracks[0].duration, Some(200));
+        assert_eq!(tracks[1].path, PathBuf::from("/music/two.mp3"));
+        assert_eq!(tracks[1].title.as_deref(), Some("Two"));
+        assert_eq!(tracks[1].duration, None);
+    }
+
+    #[test]
+    fn read_versioned_header() {
+        let content = header(PLAYLIST_FORMAT) + "description = \"x\"\n";
+        let table = read_versioned(content.as_bytes(), PLAYLIST_FORMAT)
+            .unwrap()
+            .unwrap();
+        assert_eq!(table["description"].as_str(), Some("x"));
+
+        // someone else's toml file
+        let content = header(STATE_FORMAT);
+        assert!(read_versioned(content.as_bytes(), PLAYLIST_FORMAT)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn read_versioned_errors() {
+        let content = header(PLAYLIST_FORMAT) + "description = \n";
+        assert!(read_versioned(content.as_bytes(), PLAYLIST_FORMAT).is_err());
+
+        let content = format!("format = \"{}\"\n", PLAYLIST_FORMAT);
+        assert!(read_versioned(content.as_bytes(), PLAYLIST_FORMAT).is_err());
+
+        let content = format!(
+            "format = \"{}\"\nversion = {}\n",
+            PLAYLIST_FORMAT,
+            FORMAT_VERSION + 1
+        );
+        assert!(read_versioned(content.as_bytes(), PLAYLIST_FORMAT).is_err());
+    }
+
+    #[test]
+    fn old_bincode_playlist_is_migrated() {
+        let queue: VecDeque<PathBuf> = ["/music/one.mp3", "/music/two.mp3"]
+            .into_iter()
+            .map(PathBuf::from)
+            .collect();
+        let content = bincode::serialize(&queue).unwrap();
+        assert!(read_versioned(&content, PLAYLIST_FORMAT).unwrap().is_none());
+
+        let path = playlist("old.plist", "");
+        fs::write(&path, &content).unwrap();
+        let old = parse_plist(&path).unwrap();
+        assert_eq!(
+            old.tracks.iter().map(|x| &x.path).collect::<Vec<_>>(),
+            queue.iter().collect::<Vec<_>>()
+        );
+
+        write_plist(&path, &old).unwrap();
+        assert_eq!(fs::read(path.with_extension("plist.bak")).unwrap(), content);
+        let content = fs::read(&path).unwrap();
+        assert!(read_versioned(&content, PLAYLIST_FORMAT).unwrap().is_some());
+        assert_eq!(parse_plist(&path).unwrap().tracks, old.tracks);
+    }
+}
@@ -0,0 +1,405 @@
+use std::{
+    collections::VecDeque,
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::paths;
+
+// oldest changes are forgotten after this, their copies in trash go with them
+const HISTORY_LIMIT: usize = 100;
+
+// Changes =================================================
+pub enum Change {
+    Queue {
+        before: VecDeque<PathBuf>,
+        after: VecDeque<PathBuf>,
+    },
+    // files are kept in trash while they can be restored, (path, trash) pairs,
+    // several marked playlists are deleted and restored at once
+    PlaylistsDeleted(Vec<(PathBuf, PathBuf)>),
+    // copies of the file before and after it was written, None when it didn't exist before
+    PlaylistWritten {
+        path: PathBuf,
+        before: Option<PathBuf>,
+        after: PathBuf,
+    },
+}
+
+// what undo or redo has to apply to the player, playlist files are handled right away
+pub enum Applied {
+    Queue(VecDeque<PathBuf>),
+    Playlist,
+}
+
+impl Change {
+    fn apply(&self, undo: bool) -> Result<Applied> {
+        match self {
+            Change::Queue { before, after } => {
+                Ok(Applied::Queue(if undo { before } else { after }.clone()))
+            }
+            Change::PlaylistsDeleted(files) if undo => {
+                if let Some((path, _)) = files.iter().find(|(path, _)| path.exists()) {
+                    return Err(anyhow!("Can't restore playlist, {} exists", path.display()));
+                }
+                for (path, trash) in files {
+                    fs::copy(trash, path)?;
+                }
+                Ok(Applied::Playlist)
+            }
+            Change::PlaylistsDeleted(files) => {
+                for (path, _) in files {
+                    if path.exists() {
+                        fs::remove_file(path)?;
+                    }
+                }
+                Ok(Applied::Playlist)
+            }
+            Change::PlaylistWritten { path, before, .. } if undo => {
+                match before {
+                    Some(before) => {
+                        fs::copy(before, path)?;
+                    }
+                    None if path.exists() => fs::remove_file(path)?,
+                    None => {}
+                }
+                Ok(Applied::Playlist)
+            }
+            Change::PlaylistWritten { path, after, .. } => {
+                fs::copy(after, path)?;
+                Ok(Applied::Playlist)
+            }
+        }
+    }
+
+    // copies in trash that only this change uses
+    fn forget(self) {
+        let copies = match self {
+            Change::Queue { .. } => vec![],
+            Change::PlaylistsDeleted(files) => files.into_iter().map(|(_, trash)| trash).collect(),
+            Change::PlaylistWritten { before, after, .. } => {
+                before.into_iter().chain([after]).collect()
+            }
+        };
+        for copy in copies {
+            let _ = fs::remove_file(copy);
+        }
+    }
+}
+
+// History =================================================
+pub struct History {
+    undo: Vec<Change>,
+    redo: Vec<Change>,
+    trash: PathBuf,
+    trash_counter: usize,
+}
+
+impl History {
+    pub fn new() -> Self {
+        History {
+            undo: vec![],
+            redo: vec![],
+            trash: History::trash_dir(),
+            trash_counter: 0,
+        }
+    }
+
+    // new change makes redo impossible
+    pub fn push(&mut self, change: Change) {
+        self.redo.drain(..).for_each(Change::forget);
+        self.undo.push(change);
+
+        if self.undo.len() > HISTORY_LIMIT {
+            self.undo.remove(0).forget();
+        }
+    }
+
+    pub fn undo(&mut self) -> Result<Option<Applied>> {
+        let Some(change) = self.undo.pop() else {
+            return Ok(None);
+        };
+
+        // change stays where it was when files can't be restored
+        match change.apply(true) {
+            Ok(applied) => {
+                self.redo.push(change);
+                Ok(Some(applied))
+            }
+            Err(err) => {
+                self.undo.push(change);
+                Err(err)
+            }
+        }
+    }
+
+    pub fn redo(&mut self) -> Result<Option<Applied>> {
+        let Some(change) = self.redo.pop() else {
+            return Ok(None);
+        };
+
+        match change.apply(false) {
+            Ok(applied) => {
+                self.undo.push(change);
+                Ok(Some(applied))
+            }
+            Err(err) => {
+                self.redo.push(change);
+                Err(err)
+            }
+        }
+    }
+
+    // Trash ===============================================
+    // one per process, so the panic hook can remove it without the workspace lock,
+    // the runtime directory isn't shared with other users like /tmp is
+    pub fn trash_dir() -> PathBuf {
+        paths::runtime_dir()
+            .unwrap_or_else(|_| env::temp_dir())
+            .join(format!("trash-{}", process::id()))
+    }
+
+    // made on first use, a folder that is already there belongs to someone else
+    fn create_trash(&self) -> Result<()> {
+        if let Some(dir) = self.trash.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut builder = fs::DirBuilder::new();
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+
+        builder
+            .create(&self.trash)
+            .map_err(|e| anyhow!("Can't create trash folder {}: {}", self.trash.display(), e))
+    }
+
+    // copy of the file that lives until its change leaves history or the session ends
+    pub fn keep_copy(&mut self, path: &Path) -> Result<PathBuf> {
+        if self.trash_counter == 0 {
+            self.create_trash()?;
+        }
+
+        self.trash_counter += 1;
+        let name = path
+            .file_name()
+            .and_then(|x| x.to_str())
+            .unwrap_or_default();
+        let copy = self.trash.join(format!("{}-{}", self.trash_counter, name));
+        fs::copy(path, &copy)?;
+
+        Ok(copy)
+    }
+
+    // moves playlists into trash and records them as one change,
+    // the ones deleted before an error are still recorded
+    pub fn delete_playlists(&mut self, paths: &[PathBuf]) -> Result<()> {
+        let mut files = vec![];
+        let mut result = Ok(());
+        for path in paths {
+            result = self.keep_copy(path).and_then(|trash| {
+                fs::remove_file(path)?;
+                files.push((path.clone(), trash));
+                Ok(())
+            });
+            if result.is_err() {
+                break;
+            }
+        }
+
+        if !files.is_empty() {
+            self.push(Change::PlaylistsDeleted(files));
+        }
+        result
+    }
+
+    // runs write and records the playlist file before and after it
+    pub fn write_playlist(
+        &mut self,
+        path: &Path,
+        write: impl FnOnce() -> Result<()>,
+    ) -> Result<()> {
+        let before = if path.exists() {
+            Some(self.keep_copy(path)?)
+        } else {
+            None
+        };
+        write()?;
+        let after = self.keep_copy(path)?;
+
+        self.push(Change::PlaylistWritten {
+            path: path.to_path_buf(),
+            before,
+            after,
+        });
+
+        Ok(())
+    }
+
+    // called on exit and after a panic, nothing can be undone after that
+    pub fn clear_trash(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        let _ = fs::remove_dir_all(&self.trash);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(paths: &[&str]) -> VecDeque<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    // the trash of the process is shared by all tests, each one gets its own
+    fn history(folder: &Path) -> History {
+        let _ = fs::remove_dir_all(folder);
+        fs::create_dir_all(folder).unwrap();
+        History {
+            trash: folder.join("trash"),
+            ..History::new()
+        }
+    }
+
+    fn test_folder(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("musicshell-test-{}-{}", process::id(), name))
+    }
+
+    fn applied_queue(applied: Result<Option<Applied>>) -> Option<VecDeque<PathBuf>> {
+        match applied.unwrap() {
+            Some(Applied::Queue(queue)) => Some(queue),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn undo_redo_queue() {
+        let mut history = History::new();
+        assert!(history.undo().unwrap().is_none());
+
+        history.push(Change::Queue {
+            before: queue(&[]),
+            after: queue(&["a"]),
+        });
+        history.push(Change::Queue {
+            before: queue(&["a"]),
+            after: queue(&["a", "b"]),
+        });
+
+        assert_eq!(applied_queue(history.undo()), Some(queue(&["a"])));
+        assert_eq!(applied_queue(history.undo()), Some(queue(&[])));
+        assert!(history.undo().unwrap().is_none());
+        assert_eq!(applied_queue(history.redo()), Some(queue(&["a"])));
+
+        // a new change drops what could be redone
+        history.push(Change::Queue {
+            before: queue(&["a"]),
+            after: queue(&["c"]),
+        });
+        assert!(history.redo().unwrap().is_none());
+        assert_eq!(applied_queue(history.undo()), Some(queue(&["a"])));
+    }
+
+    #[test]
+    fn history_limit() {
+        let mut history = History::new();
+        for _ in 0..HISTORY_LIMIT + 10 {
+            history.push(Change::Queue {
+                before: queue(&[]),
+                after: queue(&[]),
+            });
+        }
+        assert_eq!(history.undo.len(), HISTORY_LIMIT);
+    }
+
+    #[test]
+    fn undo_deleted_playlists() {
+        let folder = test_folder("deleted");
+        let mut history = history(&folder);
+        let paths = [folder.join("one.plist"), folder.join("two.plist")];
+        for path in &paths {
+            fs::write(path, path.to_str().unwrap()).unwrap();
+        }
+
+        history.delete_playlists(&paths).unwrap();
+        assert!(paths.iter().all(|x| !x.exists()));
+
+        assert!(matches!(history.undo().unwrap(), Some(Applied::Playlist)));
+        for path in &paths {
+            assert_eq!(fs::read_to_string(path).unwrap(), path.to_str().unwrap());
+        }
+
+        assert!(matches!(history.redo().unwrap(), Some(Applied::Playlist)));
+        assert!(paths.iter().all(|x| !x.exists()));
+
+        // a file in the way keeps the change in history
+        history.undo().unwrap();
+        history.redo().unwrap();
+        fs::write(&paths[1], "new").unwrap();
+        assert!(history.undo().is_err());
+        assert_eq!(history.undo.len(), 1);
+        assert!(!paths[0].exists());
+
+        history.clear_trash();
+        assert!(!folder.join("trash").exists());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn undo_written_playlist() {
+        let folder = test_folder("written");
+        let mut history = history(&folder);
+        let path = folder.join("one.plist");
+
+        history
+            .write_playlist(&path, || Ok(fs::write(&path, "first")?))
+            .unwrap();
+        history
+            .write_playlist(&path, || Ok(fs::write(&path, "second")?))
+            .unwrap();
+
+        history.undo().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first");
+        history.undo().unwrap();
+        assert!(!path.exists());
+        history.redo().unwrap();
+        history.redo().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+
+        history.clear_trash();
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn trash_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let folder = test_folder("private");
+        let mut history = history(&folder);
+        let path = folder.join("one.plist");
+        fs::write(&path, "").unwrap();
+
+        history.keep_copy(&path).unwrap();
+        let mode = folder
+            .join("trash")
+            .metadata()
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        // someone else's folder isn't used
+        let mut other = History {
+            trash: folder.join("trash"),
+            ..History::new()
+        };
+        assert!(other.keep_copy(&path).is_err());
+
+        history.clear_trash();
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
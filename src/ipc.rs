@@ -0,0 +1,320 @@
+use std::{
+    fs,
+    io::{BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    thread,
+};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    actions::{self, Action},
+    command,
+    player::Player,
+    workspace::Workspace,
+};
+
+pub const CTL_USAGE: &str = "\
+Usage: musicshell ctl <COMMAND>
+
+Commands:
+  play-pause, play, pause, next
+  seek <TIME>                   83, 1:23 or 1:02:03
+  volume <0-100>
+  enqueue [--next] <PATHS...>   add to the queue, or play right after the current track
+  open <PATHS...>               replace the queue
+  load-playlist <NAME>
+  run <COMMAND>                 anything the `:` command line takes
+  status, queue                 print as JSON
+  quit";
+
+// Protocol ================================================
+// one JSON object per line both ways, answers are {"ok": true, ...}
+// or {"ok": false, "error": "..."}
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Request {
+    PlayPause,
+    Play,
+    Pause,
+    Next,
+    // seconds from the start of the track
+    Seek {
+        position: usize,
+    },
+    Volume {
+        percent: usize,
+    },
+    // relative paths are taken from the directory the player was started in
+    Enqueue {
+        paths: Vec<PathBuf>,
+        #[serde(default)]
+        next: bool,
+    },
+    Open {
+        paths: Vec<PathBuf>,
+    },
+    LoadPlaylist {
+        name: String,
+    },
+    Run {
+        line: String,
+    },
+    Status,
+    Queue,
+    Quit,
+}
+
+// Server ==================================================
+// socket left by a crashed instance is replaced, a live one is an error
+pub fn bind(path: &Path) -> Result<UnixListener> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(anyhow!(
+                "Another musicshell is running, `musicshell ctl` talks to that one"
+            ));
+        }
+        fs::remove_file(path)?;
+    }
+
+    Ok(UnixListener::bind(path)?)
+}
+
+// every client gets its own thread, requests run like key presses
+pub fn serve(
+    listener: UnixListener,
+    workspace: Arc<RwLock<Workspace>>,
+    player: Arc<RwLock<Player>>,
+    save_file: PathBuf,
+) {
+    for stream in listener.incoming() {
+        if !workspace.read().unwrap().running {
+            break;
+        }
+        let Ok(stream) = stream else {
+            continue;
+        };
+
+        let (workspace, player, save_file) = (
+            Arc::clone(&workspace),
+            Arc::clone(&player),
+            save_file.clone(),
+        );
+        thread::spawn(move || handle(stream, &workspace, &player, &save_file));
+    }
+}
+
+fn handle(
+    stream: UnixStream,
+    workspace: &Arc<RwLock<Workspace>>,
+    player: &Arc<RwLock<Player>>,
+    save_file: &Path,
+) {
+    let Ok(reader) = stream.try_clone() else {
+        return;
+    };
+    let mut writer = stream;
+
+    for line in BufReader::new(reader).lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = serde_json::from_str::<Request>(&line)
+            .map_err(|e| anyhow!(e))
+            .and_then(|request| respond(request, workspace, player, save_file))
+            .unwrap_or_else(|e| json!({ "ok": false, "error": e.to_string() }));
+        if writeln!(writer, "{}", response).is_err() {
+            break;
+        }
+    }
+}
+
+fn respond(
+    request: Request,
+    workspace: &Arc<RwLock<Workspace>>,
+    player: &Arc<RwLock<Player>>,
+    save_file: &Path,
+) -> Result<Value> {
+    let (action, count) = match request {
+        Request::PlayPause => (Action::TogglePause, 1),
+        Request::Play => (Action::Play, 1),
+        Request::Pause => (Action::Pause, 1),
+        Request::Next => (Action::Skip, 1),
+        Request::Seek { position } => (Action::Seek(position), 1),
+        Request::Volume { percent } => (Action::SetVolume(percent), 1),
+        Request::Enqueue { paths, next: false } => (Action::Enqueue(paths), 1),
+        Request::Enqueue { paths, next: true } => (Action::EnqueueNext(paths), 1),
+        Request::Open { paths } => (Action::Open(paths), 1),
+        Request::LoadPlaylist { name } => {
+            let path = command::find_playlist(&name, &workspace.read().unwrap().tree)?;
+            (Action::LoadPlaylist(path), 1)
+        }
+        Request::Run { line } => {
+            let parsed = command::parse(&line, &workspace.read().unwrap().tree)?;
+            parsed.unwrap_or((Action::None, 1))
+        }
+        Request::Status => return Ok(status(player)),
+        Request::Queue => return Ok(queue(player)),
+        Request::Quit => (Action::Exit, 1),
+    };
+
+    if action == Action::Exit {
+        actions::exit(workspace, player, save_file)?;
+    } else {
+        action.perform_remote(Arc::clone(workspace), Arc::clone(player), count)?;
+    }
+
+    Ok(json!({ "ok": true }))
+}
+
+fn status(player: &Arc<RwLock<Player>>) -> Value {
+    let player = player.read().unwrap();
+    let current = player.current();
+
+    json!({
+        "ok": true,
+        "track": current.and_then(|i| player.queue.get(i)),
+        "name": player.now_playing,
+        "position": player.get_duration_current(),
+        "duration": player.get_duration_total(),
+        "paused": player.is_paused(),
+        "volume": player.get_volume_percantage(),
+        "repeat": player.repeat,
+        "index": current,
+        "queue_length": player.queue.len(),
+    })
+}
+
+fn queue(player: &Arc<RwLock<Player>>) -> Value {
+    let player = player.read().unwrap();
+
+    json!({
+        "ok": true,
+        "queue": player.queue,
+        "index": player.current(),
+    })
+}
+
+// Client ==================================================
+// `musicshell ctl status`, only status and queue print something
+pub fn client(socket: &Path, args: &[String]) -> Result<()> {
+    if args.is_empty() || ["-h", "--help", "help"].contains(&args[0].as_str()) {
+        println!("{}", CTL_USAGE);
+        return Ok(());
+    }
+    let request = parse_request(args)?;
+
+    let stream = UnixStream::connect(socket)
+        .map_err(|e| anyhow!("musicshell is not running ({}: {})", socket.display(), e))?;
+    let line = send(stream, &request)?;
+    if matches!(request, Request::Status | Request::Queue) {
+        println!("{}", line.trim_end());
+    }
+
+    Ok(())
+}
+
+// paths are played after the current track, or added to the end of the queue,
+// false when there is no instance to take them
+pub fn forward(socket: &Path, paths: &[PathBuf], enqueue: bool) -> Result<bool> {
+    let Ok(stream) = UnixStream::connect(socket) else {
+        return Ok(false);
+    };
+
+    let paths = paths
+        .iter()
+        .map(|path| path.canonicalize())
+        .collect::<Result<Vec<_>, _>>()?;
+    let count = paths.len();
+    send(
+        stream,
+        &Request::Enqueue {
+            paths,
+            next: !enqueue,
+        },
+    )?;
+    println!("Sent {} path(s) to the running musicshell", count);
+
+    Ok(true)
+}
+
+// answer line, errors from the player become Err
+fn send(mut stream: UnixStream, request: &Request) -> Result<String> {
+    writeln!(stream, "{}", serde_json::to_string(request)?)?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    let response: Value =
+        serde_json::from_str(&line).map_err(|_| anyhow!("musicshell closed the connection"))?;
+
+    if response["ok"] != true {
+        return Err(anyhow!(
+            "{}",
+            response["error"].as_str().unwrap_or("Unknown error")
+        ));
+    }
+
+    Ok(line)
+}
+
+fn parse_request(args: &[String]) -> Result<Request> {
+    let Some((name, rest)) = args.split_first() else {
+        return Err(anyhow!("ctl expects a command"));
+    };
+    let joined = rest.join(" ");
+
+    // the player can run in another directory
+    let paths = || -> Result<Vec<PathBuf>> {
+        let paths: Vec<PathBuf> = rest
+            .iter()
+            .filter(|x| *x != "--next")
+            .map(|x| fs::canonicalize(x).map_err(|e| anyhow!("Can't open {}: {}", x, e)))
+            .collect::<Result<_>>()?;
+        if paths.is_empty() {
+            return Err(anyhow!("{} expects paths", name));
+        }
+        Ok(paths)
+    };
+
+    let request = match name.as_str() {
+        "play-pause" => Request::PlayPause,
+        "play" => Request::Play,
+        "pause" => Request::Pause,
+        "next" => Request::Next,
+        "seek" => Request::Seek {
+            position: command::parse_time(&joined)
+                .ok_or_else(|| anyhow!("seek expects a time like 83 or 1:23"))?,
+        },
+        "volume" => Request::Volume {
+            percent: joined
+                .parse()
+                .ok()
+                .filter(|x| *x <= 100)
+                .ok_or_else(|| anyhow!("volume expects 0 to 100"))?,
+        },
+        "enqueue" => Request::Enqueue {
+            paths: paths()?,
+            next: rest.iter().any(|x| x == "--next"),
+        },
+        "open" => Request::Open { paths: paths()? },
+        "load-playlist" => Request::LoadPlaylist { name: joined },
+        "run" => Request::Run { line: joined },
+        "status" => Request::Status,
+        "queue" => Request::Queue,
+        "quit" => Request::Quit,
+        _ => return Err(anyhow!("Unknown command \"{}\"\n\n{}", name, CTL_USAGE)),
+    };
+
+    Ok(request)
+}
@@ -0,0 +1,624 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, Instant},
+};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers, MediaKeyCode, ModifierKeyCode};
+
+use crate::{
+    actions::Action,
+    workspace::{TreeState, Windows, Workspace},
+};
+
+// counts above this are cut, "99999j" shouldn't freeze the ui
+pub const COUNT_LIMIT: usize = 999;
+
+// Contexts ================================================
+pub const CONTEXT_NAMES: [&str; 7] = [
+    "global",
+    "files",
+    "queue",
+    "playlists",
+    "editor",
+    "theme_select",
+    "report",
+];
+
+// view or popup a binding works in, everything falls back to global
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Context {
+    Global,
+    Files,
+    Queue,
+    Playlists,
+    Editor,
+    ThemeSelect,
+    Report,
+}
+
+impl Context {
+    pub fn from_str(name: &str) -> Option<Self> {
+        match name {
+            "global" => Some(Context::Global),
+            "files" => Some(Context::Files),
+            "queue" => Some(Context::Queue),
+            "playlists" => Some(Context::Playlists),
+            "editor" => Some(Context::Editor),
+            "theme_select" => Some(Context::ThemeSelect),
+            "report" => Some(Context::Report),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        CONTEXT_NAMES[self as usize]
+    }
+
+    // other popups, like errors, only take global bindings
+    pub fn current(workspace: &Workspace) -> Self {
+        match workspace.window {
+            Windows::None => match workspace.tree.state {
+                TreeState::Files => Context::Files,
+                TreeState::Queue => Context::Queue,
+                TreeState::Playlists => Context::Playlists,
+                TreeState::Editor => Context::Editor,
+            },
+            Windows::ThemeSelect => Context::ThemeSelect,
+            Windows::Report(..) => Context::Report,
+            _ => Context::Global,
+        }
+    }
+
+    // one of the views, no popup in the way
+    pub fn is_view(self) -> bool {
+        matches!(
+            self,
+            Context::Files | Context::Queue | Context::Playlists | Context::Editor
+        )
+    }
+}
+
+// Keymap ==================================================
+#[derive(Clone)]
+struct Binding {
+    action: Action,
+    // config section it came from, for help
+    section: String,
+}
+
+// line of help window
+pub enum HelpLine {
+    Section(String),
+    Binding(String, String),
+}
+
+// bindings of one or more key presses, like "q" or "gg", for every context
+#[derive(Clone, Default)]
+pub struct Keymap {
+    bindings: HashMap<Context, HashMap<Vec<KeyEvent>, Binding>>,
+}
+
+impl Keymap {
+    pub fn insert(&mut self, context: Context, section: &str, keys: Vec<KeyEvent>, action: Action) {
+        let binding = Binding {
+            action,
+            section: section.to_string(),
+        };
+        self.bindings
+            .entry(context)
+            .or_default()
+            .insert(keys, binding);
+    }
+
+    // binding of the context wins over the global one
+    pub fn get(&self, context: Context, keys: &[KeyEvent]) -> Option<&Action> {
+        [context, Context::Global]
+            .iter()
+            .find_map(|x| self.bindings.get(x).and_then(|x| x.get(keys)))
+            .map(|x| &x.action)
+    }
+
+    // some longer binding starts with these keys
+    fn is_prefix(&self, context: Context, keys: &[KeyEvent]) -> bool {
+        [context, Context::Global]
+            .iter()
+            .filter_map(|x| self.bindings.get(x))
+            .flat_map(|x| x.keys())
+            .any(|x| x.len() > keys.len() && x.starts_with(keys))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Context, &str, &Vec<KeyEvent>, &Action)> {
+        self.bindings.iter().flat_map(|(context, bindings)| {
+            bindings.iter().map(move |(keys, binding)| {
+                (*context, binding.section.as_str(), keys, &binding.action)
+            })
+        })
+    }
+
+    // bindings that work in context grouped by section, the section of the context goes first,
+    // filter matches keys, action or section name
+    pub fn help(&self, context: Context, filter: &str) -> Vec<HelpLine> {
+        let filter = filter.to_lowercase();
+        let mut sections: BTreeMap<(bool, &str), Vec<(String, String)>> = BTreeMap::new();
+
+        for (binding_context, section, keys, action) in self.iter() {
+            let shadowed = binding_context == Context::Global
+                && context != Context::Global
+                && self
+                    .bindings
+                    .get(&context)
+                    .is_some_and(|x| x.contains_key(keys));
+            if (binding_context != context && binding_context != Context::Global) || shadowed {
+                continue;
+            }
+
+            let (keys, action) = (format_keys(keys), action.to_string());
+            let text = format!("{} {} {}", keys, action, section).to_lowercase();
+            if !text.contains(&filter) {
+                continue;
+            }
+
+            sections
+                .entry((binding_context == Context::Global, section))
+                .or_default()
+                .push((keys, action));
+        }
+
+        let mut result = vec![];
+        for ((_, section), mut bindings) in sections {
+            bindings.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(&b.0)));
+            result.push(HelpLine::Section(section.to_string()));
+            result.extend(
+                bindings
+                    .into_iter()
+                    .map(|(keys, action)| HelpLine::Binding(keys, action)),
+            );
+        }
+        result
+    }
+}
+
+// Pending keys ============================================
+pub enum Resolved {
+    // action and how many times to run it
+    Run(Action, usize),
+    Waiting,
+    Unbound,
+}
+
+// keys typed so far, waiting for the rest of a sequence
+#[derive(Default)]
+pub struct KeyState {
+    keys: Vec<KeyEvent>,
+    count: Option<usize>,
+    last_press: Option<Instant>,
+}
+
+impl KeyState {
+    pub fn press(&mut self, keymap: &Keymap, context: Context, key: KeyEvent) -> Resolved {
+        self.last_press = Some(Instant::now());
+
+        // Esc only drops what was typed when something is pending
+        if key.code == KeyCode::Esc && !self.is_empty() {
+            self.clear();
+            return Resolved::Waiting;
+        }
+
+        // digits are a count unless they start a binding, 0 only continues one
+        if self.keys.is_empty()
+            && let KeyCode::Char(ch) = key.code
+            && let Some(digit) = ch.to_digit(10)
+            && (key.modifiers - KeyModifiers::SHIFT).is_empty()
+            && (digit != 0 || self.count.is_some())
+            && keymap.get(context, &[key]).is_none()
+            && !keymap.is_prefix(context, &[key])
+        {
+            let count = self.count.unwrap_or(0) * 10 + digit as usize;
+            self.count = Some(count.min(COUNT_LIMIT));
+            return Resolved::Waiting;
+        }
+
+        self.keys.push(key);
+        if keymap.is_prefix(context, &self.keys) {
+            return Resolved::Waiting;
+        }
+        if let Some(action) = keymap.get(context, &self.keys) {
+            let action = action.clone();
+            return self.run(action);
+        }
+
+        // broken sequence, the last key may still mean something on its own
+        let retry = self.keys.len() > 1;
+        let count = self.count;
+        self.clear();
+        if retry {
+            self.count = count;
+            return self.press(keymap, context, key);
+        }
+
+        Resolved::Unbound
+    }
+
+    // "g" bound next to "gg" runs once nothing follows it in time
+    pub fn timeout(&mut self, keymap: &Keymap, context: Context, timeout: Duration) -> Resolved {
+        if self.is_empty() || self.last_press.is_some_and(|x| x.elapsed() < timeout) {
+            return Resolved::Waiting;
+        }
+
+        match keymap.get(context, &self.keys).cloned() {
+            Some(action) => self.run(action),
+            None => {
+                self.clear();
+                Resolved::Unbound
+            }
+        }
+    }
+
+    fn run(&mut self, action: Action) -> Resolved {
+        let count = self.count;
+        self.clear();
+
+        // count replaces the argument, "30" then RewindForward seeks 30 seconds,
+        // other actions are repeated
+        match count {
+            Some(count) => match action.with_arg(count) {
+                Some(action) => Resolved::Run(action, 1),
+                None => Resolved::Run(action, count),
+            },
+            None => Resolved::Run(action, 1),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty() && self.count.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.keys.clear();
+        self.count = None;
+    }
+
+    // shown in statusbar, like "5g"
+    pub fn display(&self) -> String {
+        let mut result = self.count.map(|x| x.to_string()).unwrap_or_default();
+        for key in &self.keys {
+            result.push_str(&format_key(key));
+        }
+        result
+    }
+}
+
+// Key names ===============================================
+// every key crossterm reports besides characters and F1-F24, media keys need
+// a terminal with kitty keyboard protocol
+const KEYS: [(&str, KeyCode); 51] = [
+    ("SPACE", KeyCode::Char(' ')),
+    ("BACKSPACE", KeyCode::Backspace),
+    ("TAB", KeyCode::Tab),
+    ("BACKTAB", KeyCode::BackTab),
+    ("DEL", KeyCode::Delete),
+    ("ENTER", KeyCode::Enter),
+    ("ESC", KeyCode::Esc),
+    ("ARROW_UP", KeyCode::Up),
+    ("ARROW_DOWN", KeyCode::Down),
+    ("ARROW_LEFT", KeyCode::Left),
+    ("ARROW_RIGHT", KeyCode::Right),
+    ("HOME", KeyCode::Home),
+    ("END", KeyCode::End),
+    ("PAGE_UP", KeyCode::PageUp),
+    ("PAGE_DOWN", KeyCode::PageDown),
+    ("INSERT", KeyCode::Insert),
+    ("NULL", KeyCode::Null),
+    ("CAPS_LOCK", KeyCode::CapsLock),
+    ("SCROLL_LOCK", KeyCode::ScrollLock),
+    ("NUM_LOCK", KeyCode::NumLock),
+    ("PRINT_SCREEN", KeyCode::PrintScreen),
+    ("PAUSE", KeyCode::Pause),
+    ("MENU", KeyCode::Menu),
+    ("KEYPAD_BEGIN", KeyCode::KeypadBegin),
+    ("MEDIA_PLAY", KeyCode::Media(MediaKeyCode::Play)),
+    ("MEDIA_PAUSE", KeyCode::Media(MediaKeyCode::Pause)),
+    ("MEDIA_PLAY_PAUSE", KeyCode::Media(MediaKeyCode::PlayPause)),
+    ("MEDIA_REVERSE", KeyCode::Media(MediaKeyCode::Reverse)),
+    ("MEDIA_STOP", KeyCode::Media(MediaKeyCode::Stop)),
+    (
+        "MEDIA_FAST_FORWARD",
+        KeyCode::Media(MediaKeyCode::FastForward),
+    ),
+    ("MEDIA_REWIND", KeyCode::Media(MediaKeyCode::Rewind)),
+    ("MEDIA_NEXT", KeyCode::Media(MediaKeyCode::TrackNext)),
+    (
+        "MEDIA_PREVIOUS",
+        KeyCode::Media(MediaKeyCode::TrackPrevious),
+    ),
+    ("MEDIA_RECORD", KeyCode::Media(MediaKeyCode::Record)),
+    ("VOLUME_DOWN", KeyCode::Media(MediaKeyCode::LowerVolume)),
+    ("VOLUME_UP", KeyCode::Media(MediaKeyCode::RaiseVolume)),
+    ("MUTE", KeyCode::Media(MediaKeyCode::MuteVolume)),
+    ("LEFT_SHIFT", KeyCode::Modifier(ModifierKeyCode::LeftShift)),
+    ("LEFT_CTRL", KeyCode::Modifier(ModifierKeyCode::LeftControl)),
+    ("LEFT_ALT", KeyCode::Modifier(ModifierKeyCode::LeftAlt)),
+    ("LEFT_SUPER", KeyCode::Modifier(ModifierKeyCode::LeftSuper)),
+    ("LEFT_HYPER", KeyCode::Modifier(ModifierKeyCode::LeftHyper)),
+    ("LEFT_META", KeyCode::Modifier(ModifierKeyCode::LeftMeta)),
+    (
+        "RIGHT_SHIFT",
+        KeyCode::Modifier(ModifierKeyCode::RightShift),
+    ),
+    (
+        "RIGHT_CTRL",
+        KeyCode::Modifier(ModifierKeyCode::RightControl),
+    ),
+    ("RIGHT_ALT", KeyCode::Modifier(ModifierKeyCode::RightAlt)),
+    (
+        "RIGHT_SUPER",
+        KeyCode::Modifier(ModifierKeyCode::RightSuper),
+    ),
+    (
+        "RIGHT_HYPER",
+        KeyCode::Modifier(ModifierKeyCode::RightHyper),
+    ),
+    ("RIGHT_META", KeyCode::Modifier(ModifierKeyCode::RightMeta)),
+    (
+        "ISO_LEVEL3_SHIFT",
+        KeyCode::Modifier(ModifierKeyCode::IsoLevel3Shift),
+    ),
+    (
+        "ISO_LEVEL5_SHIFT",
+        KeyCode::Modifier(ModifierKeyCode::IsoLevel5Shift),
+    ),
+];
+
+// prefix in "C-S-l", name in "mods" list, in the order they are printed
+const MODIFIERS: [(&str, &str, KeyModifiers); 6] = [
+    ("C", "CTRL", KeyModifiers::CONTROL),
+    ("A", "ALT", KeyModifiers::ALT),
+    ("S", "SHIFT", KeyModifiers::SHIFT),
+    ("D", "SUPER", KeyModifiers::SUPER),
+    ("H", "HYPER", KeyModifiers::HYPER),
+    ("M", "META", KeyModifiers::META),
+];
+
+pub fn key_names() -> Vec<&'static str> {
+    KEYS.iter().map(|(name, _)| *name).collect()
+}
+
+pub fn modifier_names() -> Vec<&'static str> {
+    MODIFIERS.iter().map(|(_, name, _)| *name).collect()
+}
+
+// named key, F1-F24 or a single character
+pub fn parse_key_code(key: &str) -> Option<KeyCode> {
+    if let Some((_, key_code)) = KEYS.iter().find(|(name, _)| *name == key) {
+        return Some(*key_code);
+    }
+
+    match key {
+        k if k.chars().count() == 1 => k.chars().next().map(KeyCode::Char),
+        k if k.starts_with('F') => k[1..]
+            .parse::<u8>()
+            .ok()
+            .filter(|&n| (1..=24).contains(&n))
+            .map(KeyCode::F),
+        _ => None,
+    }
+}
+
+// name from "mods" list, like "CTRL"
+pub fn parse_modifier(modifier: &str) -> Option<KeyModifiers> {
+    MODIFIERS
+        .iter()
+        .find(|(_, name, _)| *name == modifier)
+        .map(|(_, _, modifier)| *modifier)
+}
+
+// one key press with optional prefixes, like "C-S-l", "A-ENTER" or "C--"
+pub fn parse_key(key: &str) -> Option<KeyEvent> {
+    let mut modifiers = KeyModifiers::empty();
+    let mut rest = key;
+
+    while let Some((prefix, after)) = rest.split_once('-')
+        && !after.is_empty()
+        && let Some((_, _, modifier)) = MODIFIERS.iter().find(|(x, _, _)| *x == prefix)
+    {
+        modifiers.insert(*modifier);
+        rest = after;
+    }
+
+    parse_key_code(rest).map(|key_code| KeyEvent::new(key_code, modifiers))
+}
+
+// "gg", "dd", "g SPACE" or "C-x C-s", words that aren't keys are typed one character at a time
+pub fn parse_keys(keys: &str) -> Option<Vec<KeyEvent>> {
+    if let Some(key) = parse_key(keys) {
+        return Some(vec![key]);
+    }
+
+    let mut result = vec![];
+    for word in keys.split_whitespace() {
+        if let Some(key) = parse_key(word) {
+            result.push(key);
+            continue;
+        }
+        if is_misspelled_key(word) {
+            return None;
+        }
+        result.extend(
+            word.chars()
+                .map(|x| KeyEvent::new(KeyCode::Char(x), KeyModifiers::empty())),
+        );
+    }
+
+    (!result.is_empty()).then_some(result)
+}
+
+// "ESCP" or "F25" is a misspelled key name, but "GG" or "ZZ" are two keys
+fn is_misspelled_key(word: &str) -> bool {
+    if !word
+        .chars()
+        .all(|x| x.is_ascii_uppercase() || x.is_ascii_digit() || x == '_')
+    {
+        return false;
+    }
+    if word.len() > 1 && word.starts_with('F') && word[1..].chars().all(|x| x.is_ascii_digit()) {
+        return true;
+    }
+
+    word.len() > 2 && KEYS.iter().any(|(name, _)| name.starts_with(&word[..2]))
+}
+
+// key press the way parse_key reads it back
+pub fn format_key(key: &KeyEvent) -> String {
+    let mut modifiers = key.modifiers;
+    let mut key_code = key.code;
+
+    // shift is part of the character, "L" and not "S-l"
+    if let KeyCode::Char(ch) = key_code
+        && (ch.is_ascii_uppercase() || modifiers.contains(KeyModifiers::SHIFT))
+        && ch.is_ascii_alphabetic()
+    {
+        key_code = KeyCode::Char(ch.to_ascii_uppercase());
+        modifiers.remove(KeyModifiers::SHIFT);
+    }
+
+    let mut result = String::new();
+    for (prefix, _, modifier) in MODIFIERS {
+        if modifiers.contains(modifier) {
+            result.push_str(prefix);
+            result.push('-');
+        }
+    }
+
+    match (KEYS.iter().find(|(_, x)| *x == key_code), key_code) {
+        (Some((name, _)), _) => result.push_str(name),
+        (None, KeyCode::Char(ch)) => result.push(ch),
+        (None, KeyCode::F(n)) => result.push_str(&format!("F{}", n)),
+        (None, key_code) => result.push_str(&format!("{:?}", key_code)),
+    }
+    result
+}
+
+// "gg" when plain characters read back the same, "C-x C-s" otherwise
+pub fn format_keys(keys: &[KeyEvent]) -> String {
+    let words: Vec<String> = keys.iter().map(format_key).collect();
+
+    let joined = words.concat();
+    if words.iter().all(|x| x.chars().count() == 1) && parse_keys(&joined).as_deref() == Some(keys)
+    {
+        return joined;
+    }
+
+    words.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(key_code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(key_code, modifiers)
+    }
+
+    fn char_key(ch: char) -> KeyEvent {
+        key(KeyCode::Char(ch), KeyModifiers::empty())
+    }
+
+    #[test]
+    fn parse_key_with_modifiers() {
+        assert_eq!(
+            parse_key("C-S-l"),
+            Some(key(
+                KeyCode::Char('l'),
+                KeyModifiers::CONTROL | KeyModifiers::SHIFT
+            ))
+        );
+        assert_eq!(
+            parse_key("C--"),
+            Some(key(KeyCode::Char('-'), KeyModifiers::CONTROL))
+        );
+        assert_eq!(parse_key("-"), Some(char_key('-')));
+        assert_eq!(
+            parse_key("A-ENTER"),
+            Some(key(KeyCode::Enter, KeyModifiers::ALT))
+        );
+        assert_eq!(parse_key("X-l"), None);
+    }
+
+    #[test]
+    fn parse_function_keys() {
+        for n in 1..=24 {
+            assert_eq!(
+                parse_key(&format!("F{}", n)),
+                Some(key(KeyCode::F(n), KeyModifiers::empty()))
+            );
+        }
+        assert_eq!(parse_key("F0"), None);
+        assert_eq!(parse_key("F25"), None);
+    }
+
+    #[test]
+    fn parse_media_keys() {
+        assert_eq!(
+            parse_key("MEDIA_PLAY_PAUSE"),
+            Some(key(
+                KeyCode::Media(MediaKeyCode::PlayPause),
+                KeyModifiers::empty()
+            ))
+        );
+        assert_eq!(
+            parse_key("VOLUME_UP"),
+            Some(key(
+                KeyCode::Media(MediaKeyCode::RaiseVolume),
+                KeyModifiers::empty()
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_sequences() {
+        assert_eq!(parse_keys("gg"), Some(vec![char_key('g'), char_key('g')]));
+        assert_eq!(parse_keys("G G"), Some(vec![char_key('G'), char_key('G')]));
+        assert_eq!(parse_keys("GG"), Some(vec![char_key('G'), char_key('G')]));
+        assert_eq!(
+            parse_keys("g SPACE"),
+            Some(vec![char_key('g'), char_key(' ')])
+        );
+        assert_eq!(
+            parse_keys("C-x C-s"),
+            Some(vec![
+                key(KeyCode::Char('x'), KeyModifiers::CONTROL),
+                key(KeyCode::Char('s'), KeyModifiers::CONTROL),
+            ])
+        );
+        assert_eq!(parse_keys("ESCP"), None);
+        assert_eq!(parse_keys("F25"), None);
+        assert_eq!(parse_keys(""), None);
+    }
+
+    #[test]
+    fn format_reads_back() {
+        for name in [
+            "C-S-l",
+            "C--",
+            "-",
+            "F13",
+            "F24",
+            "MEDIA_NEXT",
+            "MUTE",
+            "A-ENTER",
+        ] {
+            let parsed = parse_key(name).unwrap();
+            assert_eq!(parse_key(&format_key(&parsed)), Some(parsed), "{}", name);
+        }
+        assert_eq!(
+            format_key(&key(KeyCode::Char('l'), KeyModifiers::SHIFT)),
+            "L"
+        );
+
+        for keys in ["gg", "G G", "GG", "g SPACE", "C-x C-s", "F13 F24"] {
+            let parsed = parse_keys(keys).unwrap();
+            assert_eq!(parse_keys(&format_keys(&parsed)), Some(parsed), "{}", keys);
+        }
+        assert_eq!(format_keys(&parse_keys("gg").unwrap()), "gg");
+    }
+}
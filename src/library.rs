@@ -0,0 +1,534 @@
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::BufReader,
+    iter::Peekable,
+    path::{Path, PathBuf},
+    str::Chars,
+    sync::{LazyLock, Mutex},
+    time::SystemTime,
+};
+
+use rodio::{Decoder, Source};
+use serde::Deserialize;
+use symphonia::core::{
+    formats::FormatOptions,
+    io::MediaSourceStream,
+    meta::{MetadataOptions, MetadataRevision, StandardTagKey},
+    probe::Hint,
+};
+
+use crate::{actions::MUSIC_EXTENSIONS, formats::PlaylistTrack};
+
+// Track metadata ==========================================
+// tags read before by path and modification time, smart playlists go through the whole library
+static TAG_CACHE: LazyLock<Mutex<HashMap<PathBuf, (SystemTime, TrackInfo)>>> =
+    LazyLock::new(Default::default);
+
+#[derive(Debug, Default, Clone)]
+pub struct TrackInfo {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub genre: Option<String>,
+    pub year: Option<u32>,
+    pub track_number: Option<u32>,
+    pub duration: Option<usize>,
+}
+
+impl TrackInfo {
+    // never fails, tags which can't be read are just left empty
+    pub fn read(path: &Path) -> Self {
+        let mut info = TrackInfo::default();
+
+        let Ok(file) = File::open(path) else {
+            return info;
+        };
+        let mss = MediaSourceStream::new(Box::new(file), Default::default());
+        let mut hint = Hint::new();
+        if let Some(ext) = path.extension().and_then(|x| x.to_str()) {
+            hint.with_extension(ext);
+        }
+
+        if let Ok(mut probed) = symphonia::default::get_probe().format(
+            &hint,
+            mss,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        ) {
+            if let Some(revision) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+                info.apply_tags(revision);
+            }
+            if let Some(revision) = probed.format.metadata().current() {
+                info.apply_tags(revision);
+            }
+
+            info.duration = probed.format.default_track().and_then(|track| {
+                let frames = track.codec_params.n_frames?;
+                let time_base = track.codec_params.time_base?;
+                Some(time_base.calc_time(frames).seconds as usize)
+            });
+        }
+
+        if info.duration.is_none() {
+            info.duration = duration(path);
+        }
+
+        info
+    }
+
+    // same as read, but a file that didn't change since the last call isn't opened again
+    pub fn read_cached(path: &Path) -> Self {
+        let Ok(modified) = path.metadata().and_then(|x| x.modified()) else {
+            return TrackInfo::read(path);
+        };
+        if let Some((time, info)) = TAG_CACHE.lock().unwrap().get(path)
+            && *time == modified
+        {
+            return info.clone();
+        }
+
+        let info = TrackInfo::read(path);
+        TAG_CACHE
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), (modified, info.clone()));
+        info
+    }
+
+    // "Artist - Title", file name when there are no tags
+    pub fn display_title(&self, path: &Path) -> String {
+        match (&self.artist, &self.title) {
+            (Some(artist), Some(title)) => format!("{} - {}", artist, title),
+            (None, Some(title)) => title.clone(),
+            _ => path
+                .file_stem()
+                .and_then(|x| x.to_str())
+                .unwrap_or_default()
+                .to_string(),
+        }
+    }
+
+    fn apply_tags(&mut self, revision: &MetadataRevision) {
+        for tag in revision.tags() {
+            // RIFF INFO strings in wav files keep their terminating NUL
+            let value = tag.value.to_string().trim_end_matches('\0').to_string();
+            match tag.std_key {
+                Some(StandardTagKey::TrackTitle) => self.title = Some(value),
+                Some(StandardTagKey::Artist) => self.artist = Some(value),
+                Some(StandardTagKey::Album) => self.album = Some(value),
+                Some(StandardTagKey::Genre) => self.genre = Some(value),
+                Some(StandardTagKey::Date) | Some(StandardTagKey::ReleaseDate) => {
+                    // dates come as "1959", "1959-08-17" and so on
+                    self.year = value.get(..4).and_then(|x| x.parse().ok()).or(self.year);
+                }
+                Some(StandardTagKey::TrackNumber) => {
+                    // "3/12" is a valid track number too
+                    self.track_number = value.split('/').next().and_then(|x| x.trim().parse().ok());
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+// duration through the decoder, works for formats symphonia can't probe
+pub fn duration(path: &Path) -> Option<usize> {
+    let file = File::open(path).ok()?;
+    let source = Decoder::new(BufReader::new(file)).ok()?;
+    source.total_duration().map(|x| x.as_secs() as usize)
+}
+
+// days passed since the file appeared on disk
+pub fn days_since_added(path: &Path) -> Option<u64> {
+    let metadata = path.metadata().ok()?;
+    let added = metadata.created().or_else(|_| metadata.modified()).ok()?;
+    let elapsed = SystemTime::now().duration_since(added).ok()?;
+
+    Some(elapsed.as_secs() / (60 * 60 * 24))
+}
+
+// "3 tracks are missing: ..." or None when everything is in place
+pub fn missing_report<'a>(paths: impl IntoIterator<Item = &'a PathBuf>) -> Option<String> {
+    let missing: Vec<&PathBuf> = paths.into_iter().filter(|path| !path.exists()).collect();
+    if missing.is_empty() {
+        return None;
+    }
+
+    let mut report = format!("{} tracks are missing:", missing.len());
+    for path in missing.iter().take(5) {
+        report += &format!("\n{}", path.display());
+    }
+    if missing.len() > 5 {
+        report += &format!("\n...and {} more", missing.len() - 5);
+    }
+
+    Some(report)
+}
+
+pub fn is_music_file(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .and_then(|x| x.to_str())
+            .is_some_and(|x| MUSIC_EXTENSIONS.contains(&x))
+}
+
+// Library scanning ========================================
+// recursive walk that follows symlinks, directories already visited are skipped
+// so symlink loops can't hang the scan
+pub fn scan(root: &Path) -> Vec<PathBuf> {
+    scan_with_progress(root, &mut |_| {})
+}
+
+// progress gets number of files found so far, called every PROGRESS_STEP files
+pub fn scan_with_progress(root: &Path, progress: &mut dyn FnMut(usize)) -> Vec<PathBuf> {
+    let mut result = vec![];
+    let mut visited = HashSet::new();
+    scan_dir(root, &mut visited, &mut result, progress);
+    result.sort_by(|a, b| natural_cmp_paths(a, b));
+
+    result
+}
+
+pub const PROGRESS_STEP: usize = 200;
+
+fn scan_dir(
+    dir: &Path,
+    visited: &mut HashSet<PathBuf>,
+    result: &mut Vec<PathBuf>,
+    progress: &mut dyn FnMut(usize),
+) {
+    let Ok(canonical) = dir.canonicalize() else {
+        return;
+    };
+    if !visited.insert(canonical) {
+        return;
+    }
+
+    let Ok(entries) = dir.read_dir() else {
+        return;
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        if path.is_dir() {
+            scan_dir(&path, visited, result, progress);
+        } else if is_music_file(&path) {
+            result.push(path);
+            if result.len().is_multiple_of(PROGRESS_STEP) {
+                progress(result.len());
+            }
+        }
+    }
+}
+
+// "track 2" goes before "track 10", case only matters when names are equal otherwise
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    let mut tie = Ordering::Equal;
+
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return tie,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x = take_number(&mut a);
+                let y = take_number(&mut b);
+                // leading zeros don't matter, longer number is bigger
+                let (x_trim, y_trim) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                let ordering = x_trim
+                    .len()
+                    .cmp(&y_trim.len())
+                    .then_with(|| x_trim.cmp(y_trim));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                tie = tie.then_with(|| x.len().cmp(&y.len()));
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.to_lowercase().cmp(y.to_lowercase());
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                tie = tie.then_with(|| x.cmp(&y));
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+fn take_number(chars: &mut Peekable<Chars>) -> String {
+    let mut number = String::new();
+    while let Some(ch) = chars.next_if(|ch| ch.is_ascii_digit()) {
+        number.push(ch);
+    }
+
+    number
+}
+
+// compared by components so "a/b" goes before "a b/c"
+pub fn natural_cmp_paths(a: &Path, b: &Path) -> Ordering {
+    let mut a = a.components();
+    let mut b = b.components();
+
+    loop {
+        match (a.next(), b.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) => {
+                let ordering = natural_cmp(
+                    &x.as_os_str().to_string_lossy(),
+                    &y.as_os_str().to_string_lossy(),
+                );
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+        }
+    }
+}
+
+// Queue filter ============================================
+// [queue_filter]
+// extensions = ["mp3", "ogg"]
+// min_duration = 30
+// pattern = "*live*"
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct QueueFilter {
+    #[serde(default)]
+    pub extensions: Option<Vec<String>>,
+    #[serde(default)]
+    pub min_duration: Option<usize>,
+    #[serde(default)]
+    pub pattern: Option<String>,
+}
+
+impl QueueFilter {
+    // progress gets number of checked and total files, only called while reading durations
+    pub fn apply(
+        &self,
+        paths: Vec<PathBuf>,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Vec<PathBuf> {
+        let paths: Vec<PathBuf> = paths
+            .into_iter()
+            .filter(|path| self.matches_name(path))
+            .collect();
+
+        let Some(min_duration) = self.min_duration else {
+            return paths;
+        };
+
+        let total = paths.len();
+        let mut result = vec![];
+        for (i, path) in paths.into_iter().enumerate() {
+            if i.is_multiple_of(PROGRESS_STEP) && i != 0 {
+                progress(i, total);
+            }
+            if TrackInfo::read(&path)
+                .duration
+                .is_some_and(|duration| duration >= min_duration)
+            {
+                result.push(path);
+            }
+        }
+
+        result
+    }
+
+    fn matches_name(&self, path: &Path) -> bool {
+        if let Some(extensions) = &self.extensions {
+            let extension = path
+                .extension()
+                .and_then(|x| x.to_str())
+                .unwrap_or_default();
+            if !extensions.iter().any(|x| x.eq_ignore_ascii_case(extension)) {
+                return false;
+            }
+        }
+
+        if let Some(pattern) = &self.pattern {
+            let name = path
+                .file_name()
+                .and_then(|x| x.to_str())
+                .unwrap_or_default();
+            return glob_match(&pattern.to_lowercase(), &name.to_lowercase());
+        }
+
+        true
+    }
+}
+
+// "*" is any number of characters, "?" is exactly one
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // last "*" and the text position it was tried at
+    let mut star = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&x| x == '*')
+}
+
+// Relinking ===============================================
+// swaps "from" prefix with "to" for missing tracks whose new path exists,
+// returns number of relinked tracks
+pub fn relink_prefix(tracks: &mut [PlaylistTrack], from: &Path, to: &Path) -> usize {
+    let mut relinked = 0;
+
+    for track in tracks.iter_mut().filter(|track| !track.path.exists()) {
+        if let Ok(rest) = track.path.strip_prefix(from) {
+            let new_path = to.join(rest);
+            if new_path.exists() {
+                track.path = new_path;
+                relinked += 1;
+            }
+        }
+    }
+
+    relinked
+}
+
+// looks for missing tracks in the library, first by file name then by size and tags,
+// tracks with more than one good candidate are left alone
+pub fn relink_search(tracks: &mut [PlaylistTrack], library_folder: &Path) -> usize {
+    if tracks.iter().all(|track| track.path.exists()) {
+        return 0;
+    }
+
+    let library = scan(library_folder);
+    let mut relinked = 0;
+
+    for track in tracks.iter_mut().filter(|track| !track.path.exists()) {
+        let same_name: Vec<&PathBuf> = library
+            .iter()
+            .filter(|path| path.file_name() == track.path.file_name())
+            .collect();
+
+        let candidates: Vec<&PathBuf> = if same_name.len() == 1 {
+            same_name
+        } else if !same_name.is_empty() {
+            same_name
+                .into_iter()
+                .filter(|path| same_file(track, path))
+                .collect()
+        } else if track.size.is_some() {
+            // file was renamed too, size is checked first since reading tags is slow
+            library
+                .iter()
+                .filter(|path| path.metadata().ok().map(|x| x.len()) == track.size)
+                .filter(|path| same_file(track, path))
+                .collect()
+        } else {
+            vec![]
+        };
+
+        if let [path] = candidates[..] {
+            track.path = path.clone();
+            relinked += 1;
+        }
+    }
+
+    relinked
+}
+
+fn same_file(track: &PlaylistTrack, path: &Path) -> bool {
+    if let Some(size) = track.size
+        && path.metadata().ok().map(|x| x.len()) != Some(size)
+    {
+        return false;
+    }
+    if track.title.is_none() && track.duration.is_none() {
+        return track.size.is_some();
+    }
+
+    let info = TrackInfo::read(path);
+    track
+        .title
+        .as_ref()
+        .is_none_or(|title| *title == info.display_title(path))
+        && track
+            .duration
+            .is_none_or(|duration| Some(duration) == info.duration)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(names: &[&str]) -> Vec<String> {
+        let mut names: Vec<String> = names.iter().map(|x| x.to_string()).collect();
+        names.sort_by(|a, b| natural_cmp(a, b));
+        names
+    }
+
+    #[test]
+    fn glob_match_wildcards() {
+        assert!(glob_match("*.mp3", "song.mp3"));
+        assert!(glob_match("*.mp3", ".mp3"));
+        assert!(!glob_match("*.mp3", "song.mp3.bak"));
+        assert!(glob_match("track??.*", "track01.flac"));
+        assert!(!glob_match("track??.*", "track1.flac"));
+        assert!(glob_match("*live*", "song (live at home).ogg"));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(!glob_match("a*b*c", "aXbYc_"));
+        assert!(glob_match("**", ""));
+        assert!(!glob_match("?", ""));
+    }
+
+    #[test]
+    fn natural_cmp_numbers() {
+        assert_eq!(
+            sorted(&["track10", "track2", "track1", "Track3"]),
+            ["track1", "track2", "Track3", "track10"]
+        );
+        assert_eq!(natural_cmp("disc 2 - 9", "disc 2 - 10"), Ordering::Less);
+        assert_eq!(
+            natural_cmp("99999999999999999999a", "1b"),
+            Ordering::Greater
+        );
+    }
+
+    #[test]
+    fn natural_cmp_ties() {
+        // equal after ignoring case and leading zeros, but still a total order
+        assert_eq!(natural_cmp("01", "1"), Ordering::Greater);
+        assert_eq!(natural_cmp("Song", "song"), Ordering::Less);
+        assert_eq!(natural_cmp("song", "song"), Ordering::Equal);
+        assert_eq!(natural_cmp("song", "song 2"), Ordering::Less);
+    }
+
+    #[test]
+    fn natural_cmp_paths_by_component() {
+        assert_eq!(
+            natural_cmp_paths(Path::new("/music/a b/2.mp3"), Path::new("/music/a/10.mp3")),
+            Ordering::Greater
+        );
+        assert_eq!(
+            natural_cmp_paths(Path::new("/music/2/z.mp3"), Path::new("/music/10/a.mp3")),
+            Ordering::Less
+        );
+    }
+}
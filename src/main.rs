@@ -2,36 +2,103 @@
 use std::{
     env,
     fs::{self, File},
-    io::Write,
+    io::{self, Write},
+    panic,
     path::PathBuf,
+    process,
     sync::{Arc, RwLock},
     thread,
+    time::Duration,
 };
 
+use actions::Action;
 use anyhow::{anyhow, Ok, Result};
+use cli::Args;
+use command::CommandLine;
 use config::{Config, Parser};
+use crossterm::{
+    event::{
+        DisableMouseCapture, EnableMouseCapture, KeyboardEnhancementFlags,
+        PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    },
+    terminal, ExecutableCommand,
+};
+use history::History;
+use paths::Paths;
 use player::Player;
 use ratatui::init;
 use ui::UI;
-use workspace::{Saver, Workspace};
+use validate::Severity;
+use workspace::{Saver, Windows, Workspace};
 
 mod actions;
+mod cli;
+mod command;
 mod config;
+mod formats;
+mod history;
+#[cfg(unix)]
+mod ipc;
+mod keymap;
+mod library;
+#[cfg(target_os = "linux")]
+mod mpris;
+mod paths;
 mod player;
+mod smart;
 mod ui;
+mod validate;
 mod workspace;
 
 const SAMPLE_CONFIG: &[u8] = include_bytes!("../config_sample/config.toml");
 
 fn main() -> Result<()> {
-    // getting homedir and config path
-    let mut homedir = String::new();
-    if cfg!(target_os = "windows") {
-        homedir = env::var("USERPROFILE")?;
-    } else if cfg!(target_os = "linux") {
-        homedir = env::var("HOME")?;
+    // config and state paths, XDG directories unless overridden
+    let args = Args::parse()?;
+    if args.help {
+        println!("{}", cli::USAGE);
+        return Ok(());
+    }
+    if args.version {
+        println!("musicshell {}", env!("CARGO_PKG_VERSION"));
+        return Ok(());
+    }
+    let paths = Paths::new(args.config, args.state)?;
+
+    // talking to a running instance needs nothing else
+    if let Some(ctl) = &args.ctl {
+        #[cfg(not(unix))]
+        return Err(anyhow!("musicshell ctl only works on unix"));
+        #[cfg(unix)]
+        {
+            if let Err(e) = ipc::client(&paths.socket_file, ctl) {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+            return Ok(());
+        }
+    }
+
+    // wrong paths are reported before the terminal is taken over
+    let start_dir = match &args.start_dir {
+        Some(dir) if dir.is_dir() => dir.canonicalize()?,
+        Some(dir) => return Err(anyhow!("{} is not a directory", dir.display())),
+        None => env::current_dir()?,
+    };
+    if let Some(path) = args.paths.iter().find(|x| !x.exists()) {
+        return Err(anyhow!("{} doesn't exist", path.display()));
+    }
+
+    // files opened while musicshell already runs go to that instance
+    #[cfg(unix)]
+    if !args.paths.is_empty()
+        && !args.check_config
+        && ipc::forward(&paths.socket_file, &args.paths, args.enqueue)?
+    {
+        return Ok(());
     }
-    let config_path: PathBuf = (homedir.clone() + "/.config/musicshell/config.toml").into();
+
+    let config_path = paths.config_file.clone();
 
     if !config_path.exists() {
         fs::create_dir_all(config_path.parent().unwrap())?;
@@ -40,19 +107,49 @@ fn main() -> Result<()> {
         println!("Wrote sample config file at {}", config_path.display());
     }
 
+    // report every config problem before the terminal is taken over
+    let content = fs::read_to_string(&config_path)?;
+    let diagnostics = validate::check(&config_path, &content);
+    for diagnostic in &diagnostics {
+        eprintln!("{}", diagnostic);
+    }
+    let has_errors = diagnostics.iter().any(|x| x.severity == Severity::Error);
+    if args.check_config {
+        if diagnostics.is_empty() {
+            println!("{}: config is fine", config_path.display());
+        }
+        process::exit(if has_errors { 1 } else { 0 });
+    }
+    if has_errors {
+        eprintln!("Fix the errors above and start musicshell again");
+        process::exit(1);
+    }
+    paths.migrate()?;
+
     // init config
     let parser = Parser::new(config_path.clone())?;
     let config = Config::new(parser)?;
 
     // get playlists dir unless config is moved
     let playlists_dir = config.playlists_folder.clone();
+    let playlists_warning = config.prepare_playlists_folder()?;
 
     // init ratatui
     let terminal = init();
 
+    // media keys and an unambiguous Esc, only in terminals with kitty keyboard protocol
+    let keyboard_enhanced = terminal::supports_keyboard_enhancement().unwrap_or(false);
+    if keyboard_enhanced {
+        io::stdout().execute(PushKeyboardEnhancementFlags(
+            KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES,
+        ))?;
+    }
+    io::stdout().execute(EnableMouseCapture)?;
+
     // pointers init
-    let workspace_1 = Arc::new(RwLock::new(Workspace::new(config)?));
+    let workspace_1 = Arc::new(RwLock::new(Workspace::new(config, start_dir)?));
     let workspace_2 = Arc::clone(&workspace_1);
+    workspace_1.write().unwrap().command = CommandLine::load(paths.history_file);
 
     let (_stream, player) = Player::new().ok_or(anyhow!("Can't establish audio output"))?;
     let player_ptr_1 = Arc::new(RwLock::new(player));
@@ -60,42 +157,276 @@ fn main() -> Result<()> {
     let player_ptr_3 = Arc::clone(&player_ptr_1);
 
     // restore saved state
-    let save_file: PathBuf = (homedir + "/musicshell.dat").into();
-    if save_file.exists() {
+    let save_file = paths.state_file;
+    if let Some(dir) = save_file.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    if save_file.exists() && !args.no_restore {
         let mut mutex = player_ptr_1.write().unwrap();
         let mut mutex_workspace = workspace_1.write().unwrap();
         let data = Saver::restore(&save_file);
 
         if data.is_ok() {
             let data = data?;
+            if let Some(report) = library::missing_report(&data.queue) {
+                mutex_workspace.window = Windows::Error(report);
+            }
             mutex.queue = data.queue;
             mutex.song_index = data.song_index;
             mutex.set_volume(data.volume);
+            mutex.play_counts = data.play_counts;
+            mutex.repeat = data.repeat;
+            if let Some(track) = mutex.queue.get(mutex.song_index).cloned() {
+                mutex.resume_from(
+                    track,
+                    Duration::from_millis(data.position_ms),
+                    data.paused || !mutex_workspace.config.auto_resume,
+                );
+            }
             mutex_workspace.config.selected_theme = data.selected_theme;
-        } else {
-            fs::remove_file(&save_file)?;
+            mutex_workspace.tree.state = data.tree_view;
+        } else if let Err(e) = data {
+            // keep unreadable state around, next save would overwrite it
+            let moved = formats::move_aside(&save_file)?;
+            mutex_workspace.window = Windows::Error(format!(
+                "Can't restore saved state, it was moved to {}:\n{}",
+                moved.display(),
+                e
+            ));
+        }
+    }
+
+    // tracks from the command line replace the restored queue
+    if !args.paths.is_empty() {
+        match Action::tracks_from_paths(&workspace_1, &player_ptr_1, &args.paths) {
+            std::result::Result::Ok(mut tracks) => {
+                if args.shuffle {
+                    player::shuffle(tracks.make_contiguous());
+                }
+                let mut player = player_ptr_1.write().unwrap();
+                if args.enqueue {
+                    player.queue.extend(tracks);
+                } else {
+                    player.play_queue(tracks);
+                }
+            }
+            Err(e) => workspace_1.write().unwrap().window = Windows::Error(e.to_string()),
+        }
+    }
+    if let Some(volume) = args.volume {
+        player_ptr_1
+            .read()
+            .unwrap()
+            .set_volume(volume as f32 / 100.0);
+    }
+
+    // warnings are already printed, but the terminal is cleared by now
+    let warnings = diagnostics.len();
+    if warnings != 0 {
+        let mut mutex_workspace = workspace_1.write().unwrap();
+        if mutex_workspace.window == Windows::None {
+            mutex_workspace.window = Windows::Error(format!(
+                "Config has {} warning(s), run `musicshell --check-config` to see them",
+                warnings
+            ));
         }
     }
 
     // restore playlist list
     if playlists_dir.exists() {
         Saver::restore_playlists(Arc::clone(&workspace_1), &playlists_dir)?;
+    } else if let Some(warning) = playlists_warning {
+        let mut mutex_workspace = workspace_1.write().unwrap();
+        if mutex_workspace.window == Windows::None {
+            mutex_workspace.window = Windows::Error(warning);
+        }
     }
 
+    // crash safety
+    set_panic_hook(
+        Arc::clone(&workspace_1),
+        Arc::clone(&player_ptr_1),
+        save_file.clone(),
+        keyboard_enhanced,
+    );
+    #[cfg(unix)]
+    handle_signals(
+        Arc::clone(&workspace_1),
+        Arc::clone(&player_ptr_1),
+        save_file.clone(),
+    )?;
+    let autosave_interval = workspace_1.read().unwrap().config.autosave_interval;
+    if autosave_interval != 0 {
+        let (workspace, player, save_file) = (
+            Arc::clone(&workspace_1),
+            Arc::clone(&player_ptr_1),
+            save_file.clone(),
+        );
+        thread::spawn(move || autosave(workspace, player, save_file, autosave_interval));
+    }
+
+    let workspace = Arc::clone(&workspace_1);
+    thread::spawn(move || watch_config(workspace));
+
+    // control socket for `musicshell ctl` and scripts
+    #[cfg(unix)]
+    let socket_file = match ipc::bind(&paths.socket_file) {
+        std::result::Result::Ok(listener) => {
+            let (workspace, player, save_file) = (
+                Arc::clone(&workspace_1),
+                Arc::clone(&player_ptr_1),
+                save_file.clone(),
+            );
+            thread::spawn(move || ipc::serve(listener, workspace, player, save_file));
+            Some(paths.socket_file)
+        }
+        Err(e) => {
+            let mut mutex_workspace = workspace_1.write().unwrap();
+            if mutex_workspace.window == Windows::None {
+                mutex_workspace.window = Windows::Error(e.to_string());
+            }
+            None
+        }
+    };
+
+    // media keys and desktop widgets, musicshell works the same without a session bus
+    #[cfg(target_os = "linux")]
+    let _ = mpris::start(
+        Arc::clone(&workspace_1),
+        Arc::clone(&player_ptr_1),
+        save_file.clone(),
+    );
+
     // run program
     thread::spawn(move || player::main_loop(player_ptr_3));
     thread::spawn(move || UI::main_loop(workspace_2, player_ptr_2, terminal));
 
     let action_handler_result = actions::main_loop(workspace_1, player_ptr_1, &save_file);
+    #[cfg(unix)]
+    if let Some(socket_file) = socket_file {
+        let _ = fs::remove_file(socket_file);
+    }
     match action_handler_result {
         std::result::Result::Ok(_) => {}
         Err(e) => {
-            ratatui::restore();
+            restore(keyboard_enhanced);
             return Err(e);
         }
     }
 
+    restore(keyboard_enhanced);
+
+    Ok(())
+}
+
+fn restore(keyboard_enhanced: bool) {
+    let _ = io::stdout().execute(DisableMouseCapture);
+    if keyboard_enhanced {
+        let _ = io::stdout().execute(PopKeyboardEnhancementFlags);
+    }
     ratatui::restore();
+}
+
+// Crash safety ============================================
+fn autosave(
+    workspace: Arc<RwLock<Workspace>>,
+    player: Arc<RwLock<Player>>,
+    save_file: PathBuf,
+    interval: u64,
+) {
+    loop {
+        thread::sleep(Duration::from_secs(interval));
+        if !workspace.read().unwrap().running {
+            break;
+        }
+
+        if let Err(e) =
+            Saver::default().save(Arc::clone(&player), Arc::clone(&workspace), &save_file)
+        {
+            let mut mutex = workspace.write().unwrap();
+            if mutex.window == Windows::None {
+                mutex.window = Windows::Error(format!("Autosave failed:\n{}", e));
+            }
+        }
+    }
+}
+
+// reloads config when the file changes and "watch_config" is on
+fn watch_config(workspace: Arc<RwLock<Workspace>>) {
+    let modified = |workspace: &Arc<RwLock<Workspace>>| {
+        let file = workspace.read().unwrap().config.file.clone();
+        fs::metadata(file).and_then(|x| x.modified()).ok()
+    };
+    let mut last_modified = modified(&workspace);
+
+    loop {
+        thread::sleep(Duration::from_secs(1));
+        let watching = {
+            let mutex = workspace.read().unwrap();
+            if !mutex.running {
+                break;
+            }
+            mutex.config.watch_config
+        };
+
+        let current = modified(&workspace);
+        if current == last_modified {
+            continue;
+        }
+        last_modified = current;
+
+        if watching && let Err(e) = Action::reload_config(&workspace) {
+            // not over a prompt that is being typed in
+            let mut mutex = workspace.write().unwrap();
+            if mutex.window == Windows::None {
+                mutex.window = Windows::Error(e.to_string());
+            }
+        }
+    }
+}
+
+// state is saved and the whole program exits, a panic in player or ui thread
+// would leave it half working otherwise
+fn set_panic_hook(
+    workspace: Arc<RwLock<Workspace>>,
+    player: Arc<RwLock<Player>>,
+    save_file: PathBuf,
+    keyboard_enhanced: bool,
+) {
+    // ratatui::init already installed a hook that restores the terminal,
+    // the rest is undone in the same order as restore does
+    let previous_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let _ = Saver::default().try_save(&player, &workspace, &save_file);
+        let _ = fs::remove_dir_all(History::trash_dir());
+        let _ = io::stdout().execute(DisableMouseCapture);
+        if keyboard_enhanced {
+            let _ = io::stdout().execute(PopKeyboardEnhancementFlags);
+        }
+        previous_hook(info);
+        process::exit(101);
+    }));
+}
+
+// SIGTERM, SIGHUP and SIGINT save the state and stop the main loop,
+// terminal is restored on the usual way out of main
+#[cfg(unix)]
+fn handle_signals(
+    workspace: Arc<RwLock<Workspace>>,
+    player: Arc<RwLock<Player>>,
+    save_file: PathBuf,
+) -> Result<()> {
+    use signal_hook::{
+        consts::{SIGHUP, SIGINT, SIGTERM},
+        iterator::Signals,
+    };
+
+    let mut signals = Signals::new([SIGTERM, SIGHUP, SIGINT])?;
+    thread::spawn(move || {
+        if signals.forever().next().is_some() {
+            let _ = actions::exit(&workspace, &player, &save_file);
+        }
+    });
 
     Ok(())
 }
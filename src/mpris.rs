@@ -0,0 +1,451 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process,
+    sync::{Arc, RwLock},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use zbus::{
+    blocking::{connection, object_server::InterfaceRef, Connection},
+    fdo, interface,
+    object_server::SignalEmitter,
+    zvariant::{ObjectPath, OwnedObjectPath, Value},
+};
+
+use crate::{
+    actions::{self, Action},
+    formats,
+    library::TrackInfo,
+    player::Player,
+    workspace::Workspace,
+};
+
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.musicshell";
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+const NO_TRACK: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+// how often the player is checked for changes to announce
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// Service =================================================
+// registers on the session bus and announces changes until musicshell exits,
+// errors mean there is no session bus (tty, ssh) and musicshell runs without it
+pub fn start(
+    workspace: Arc<RwLock<Workspace>>,
+    player: Arc<RwLock<Player>>,
+    save_file: PathBuf,
+) -> Result<()> {
+    let shared = Shared {
+        workspace: Arc::clone(&workspace),
+        player: Arc::clone(&player),
+    };
+    let connection = connection::Builder::session()?
+        .serve_at(
+            OBJECT_PATH,
+            Root {
+                shared: shared.clone(),
+                save_file,
+            },
+        )?
+        .serve_at(
+            OBJECT_PATH,
+            MediaPlayer {
+                shared,
+                track: None,
+            },
+        )?
+        .build()?;
+
+    // the spec wants a unique suffix when the name is taken
+    if connection.request_name(BUS_NAME).is_err() {
+        connection.request_name(format!("{}.instance{}", BUS_NAME, process::id()))?;
+    }
+
+    let iface = connection
+        .object_server()
+        .interface::<_, MediaPlayer>(OBJECT_PATH)?;
+    thread::spawn(move || watch(connection, iface, workspace, player));
+
+    Ok(())
+}
+
+#[derive(Clone)]
+struct Shared {
+    workspace: Arc<RwLock<Workspace>>,
+    player: Arc<RwLock<Player>>,
+}
+
+impl Shared {
+    // requests run like key presses
+    fn run(&self, action: Action) -> fdo::Result<()> {
+        action
+            .perform_remote(Arc::clone(&self.workspace), Arc::clone(&self.player), 1)
+            .map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+
+    fn current(&self) -> Option<usize> {
+        self.player.read().unwrap().current()
+    }
+}
+
+fn track_id(index: usize) -> OwnedObjectPath {
+    ObjectPath::try_from(format!("/org/musicshell/track/{}", index))
+        .unwrap()
+        .into()
+}
+
+fn micros(seconds: usize) -> i64 {
+    seconds as i64 * 1_000_000
+}
+
+// Change tracking =========================================
+#[derive(PartialEq)]
+struct Snapshot {
+    track: Option<(usize, PathBuf)>,
+    status: &'static str,
+    volume: usize,
+    repeat: bool,
+    queue_length: usize,
+    position: Duration,
+}
+
+impl Snapshot {
+    fn take(player: &Player) -> Self {
+        Snapshot {
+            track: player
+                .current()
+                .and_then(|index| Some((index, player.queue.get(index)?.clone()))),
+            status: playback_status(player),
+            volume: player.get_volume_percantage(),
+            repeat: player.repeat,
+            queue_length: player.queue.len(),
+            position: player.get_position(),
+        }
+    }
+}
+
+fn playback_status(player: &Player) -> &'static str {
+    if player.now_playing.is_empty() {
+        "Stopped"
+    } else if player.is_paused() {
+        "Paused"
+    } else {
+        "Playing"
+    }
+}
+
+// PropertiesChanged for what changed since the last look, Seeked for jumps in position
+fn watch(
+    _connection: Connection,
+    iface: InterfaceRef<MediaPlayer>,
+    workspace: Arc<RwLock<Workspace>>,
+    player: Arc<RwLock<Player>>,
+) {
+    let mut last = Snapshot::take(&player.read().unwrap());
+    let mut last_time = Instant::now();
+
+    while workspace.read().unwrap().running {
+        thread::sleep(POLL_INTERVAL);
+        let snapshot = Snapshot::take(&player.read().unwrap());
+        let elapsed = last_time.elapsed();
+        last_time = Instant::now();
+
+        let track_changed = snapshot.track != last.track;
+        if track_changed {
+            // tags are read here so Metadata doesn't hit the disk on every request
+            let info = snapshot
+                .track
+                .as_ref()
+                .map(|(_, path)| TrackInfo::read(path));
+            iface.get_mut().track = snapshot.track.clone().zip(info);
+        }
+
+        let emitter = iface.signal_emitter();
+        let iface = iface.get();
+        let _ = zbus::block_on(async {
+            if track_changed {
+                iface.metadata_changed(emitter).await?;
+                iface.can_seek_changed(emitter).await?;
+            } else {
+                // paused or finished tracks don't move
+                let expected = match last.status {
+                    "Playing" => last.position + elapsed,
+                    _ => last.position,
+                };
+                if snapshot.position.abs_diff(expected) > Duration::from_secs(1) {
+                    let position = snapshot.position.as_micros() as i64;
+                    MediaPlayer::seeked(emitter, position).await?;
+                }
+            }
+
+            if snapshot.status != last.status {
+                iface.playback_status_changed(emitter).await?;
+            }
+            if snapshot.volume != last.volume {
+                iface.volume_changed(emitter).await?;
+            }
+            if snapshot.repeat != last.repeat {
+                iface.loop_status_changed(emitter).await?;
+            }
+            if snapshot.queue_length != last.queue_length {
+                iface.can_go_next_changed(emitter).await?;
+                iface.can_play_changed(emitter).await?;
+                iface.can_pause_changed(emitter).await?;
+            }
+            zbus::Result::Ok(())
+        });
+
+        last = snapshot;
+    }
+}
+
+// org.mpris.MediaPlayer2 ==================================
+struct Root {
+    shared: Shared,
+    save_file: PathBuf,
+}
+
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl Root {
+    fn raise(&self) {}
+
+    fn quit(&self) -> fdo::Result<()> {
+        actions::exit(&self.shared.workspace, &self.shared.player, &self.save_file)
+            .map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+
+    #[zbus(property)]
+    fn can_quit(&self) -> bool {
+        true
+    }
+
+    // musicshell lives in a terminal it can't bring to the front
+    #[zbus(property)]
+    fn can_raise(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn has_track_list(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn identity(&self) -> &str {
+        "musicshell"
+    }
+
+    #[zbus(property)]
+    fn supported_uri_schemes(&self) -> Vec<&str> {
+        vec!["file"]
+    }
+
+    #[zbus(property)]
+    fn supported_mime_types(&self) -> Vec<&str> {
+        vec!["audio/mpeg", "audio/wav", "audio/x-wav", "audio/ogg"]
+    }
+}
+
+// org.mpris.MediaPlayer2.Player ===========================
+struct MediaPlayer {
+    shared: Shared,
+    // tags of the current track, filled in by watch
+    track: Option<((usize, PathBuf), TrackInfo)>,
+}
+
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl MediaPlayer {
+    fn next(&self) -> fdo::Result<()> {
+        self.shared.run(Action::Skip)
+    }
+
+    // there is no history of played tracks to go back to
+    fn previous(&self) {}
+
+    fn pause(&self) -> fdo::Result<()> {
+        self.shared.run(Action::Pause)
+    }
+
+    fn play_pause(&self) -> fdo::Result<()> {
+        self.shared.run(Action::TogglePause)
+    }
+
+    // there is no stopped state, the player starts the next track as soon as
+    // nothing plays, so Stop pauses like the media widgets expect from it
+    fn stop(&self) -> fdo::Result<()> {
+        self.shared.run(Action::Pause)
+    }
+
+    fn play(&self) -> fdo::Result<()> {
+        self.shared.run(Action::Play)
+    }
+
+    // microseconds relative to the current position, past the end skips the track
+    fn seek(&self, offset: i64) -> fdo::Result<()> {
+        let (position, total) = {
+            let player = self.shared.player.read().unwrap();
+            (player.get_position(), player.get_duration_total())
+        };
+        let target = (position.as_micros() as i64 + offset).max(0);
+        if target >= micros(total) && total != 0 {
+            return self.shared.run(Action::Skip);
+        }
+        self.shared.run(Action::Seek((target / 1_000_000) as usize))
+    }
+
+    // ignored when the track changed in the meantime
+    fn set_position(&self, track_id: ObjectPath<'_>, position: i64) -> fdo::Result<()> {
+        let Some(index) = self.shared.current() else {
+            return Ok(());
+        };
+        let total = self.shared.player.read().unwrap().get_duration_total();
+        if track_id != *self::track_id(index) || position < 0 || position > micros(total) {
+            return Ok(());
+        }
+        self.shared
+            .run(Action::Seek((position / 1_000_000) as usize))
+    }
+
+    fn open_uri(&self, uri: &str) -> fdo::Result<()> {
+        let path = formats::decode_uri(uri)
+            .ok_or_else(|| fdo::Error::NotSupported("Only file:// URIs can be opened".into()))?;
+        self.shared.run(Action::Open(vec![path]))
+    }
+
+    #[zbus(signal)]
+    async fn seeked(emitter: &SignalEmitter<'_>, position: i64) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn playback_status(&self) -> &str {
+        playback_status(&self.shared.player.read().unwrap())
+    }
+
+    // Repeat replays the current track, otherwise the queue starts over after the last one
+    #[zbus(property)]
+    fn loop_status(&self) -> &str {
+        if self.shared.player.read().unwrap().repeat {
+            "Track"
+        } else {
+            "Playlist"
+        }
+    }
+
+    #[zbus(property)]
+    fn set_loop_status(&mut self, value: &str) -> fdo::Result<()> {
+        let repeat = self.shared.player.read().unwrap().repeat;
+        if (value == "Track") != repeat {
+            self.shared.run(Action::ToggleRepeat)?;
+        }
+        Ok(())
+    }
+
+    #[zbus(property)]
+    fn rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn set_rate(&mut self, _value: f64) {}
+
+    #[zbus(property)]
+    fn minimum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn maximum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn metadata(&self) -> HashMap<&str, Value<'_>> {
+        let mut metadata = HashMap::new();
+        let Some(((index, path), info)) = &self.track else {
+            metadata.insert(
+                "mpris:trackid",
+                Value::from(ObjectPath::from_static_str_unchecked(NO_TRACK)),
+            );
+            return metadata;
+        };
+
+        metadata.insert("mpris:trackid", Value::from(track_id(*index)));
+        if let Some(url) = formats::encode_uri(path) {
+            metadata.insert("xesam:url", Value::from(url));
+        }
+        metadata.insert(
+            "xesam:title",
+            Value::from(info.display_title(Path::new(path))),
+        );
+        if let Some(length) = info.duration {
+            metadata.insert("mpris:length", Value::from(micros(length)));
+        }
+        if let Some(artist) = &info.artist {
+            metadata.insert("xesam:artist", Value::from(vec![artist.as_str()]));
+        }
+        if let Some(album) = &info.album {
+            metadata.insert("xesam:album", Value::from(album.as_str()));
+        }
+        if let Some(genre) = &info.genre {
+            metadata.insert("xesam:genre", Value::from(vec![genre.as_str()]));
+        }
+        if let Some(number) = info.track_number {
+            metadata.insert("xesam:trackNumber", Value::from(number as i32));
+        }
+
+        metadata
+    }
+
+    #[zbus(property)]
+    fn volume(&self) -> f64 {
+        self.shared.player.read().unwrap().get_volume_percantage() as f64 / 100.0
+    }
+
+    #[zbus(property)]
+    fn set_volume(&mut self, value: f64) -> fdo::Result<()> {
+        let percent = (value.clamp(0.0, 1.0) * 100.0).round() as usize;
+        self.shared.run(Action::SetVolume(percent))
+    }
+
+    // not announced, clients ask for it or follow Seeked
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn position(&self) -> i64 {
+        self.shared
+            .player
+            .read()
+            .unwrap()
+            .get_position()
+            .as_micros() as i64
+    }
+
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool {
+        !self.shared.player.read().unwrap().queue.is_empty()
+    }
+
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn can_play(&self) -> bool {
+        !self.shared.player.read().unwrap().queue.is_empty()
+    }
+
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        !self.shared.player.read().unwrap().queue.is_empty()
+    }
+
+    #[zbus(property)]
+    fn can_seek(&self) -> bool {
+        self.track.is_some()
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn can_control(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,137 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+
+const APP_DIR: &str = "musicshell";
+pub const CONFIG_FILE: &str = "config.toml";
+pub const STATE_FILE: &str = "musicshell.dat";
+pub const HISTORY_FILE: &str = "command_history";
+pub const SOCKET_FILE: &str = "musicshell.sock";
+
+// Base directories ========================================
+// XDG variables are used when they hold an absolute path, the spec says to ignore relative ones
+fn home_dir() -> Result<PathBuf> {
+    let var = if cfg!(target_os = "windows") {
+        "USERPROFILE"
+    } else {
+        "HOME"
+    };
+    env::var_os(var)
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow!("${} is not set", var))
+}
+
+// "~/Music" -> "/home/user/Music", other paths are kept
+pub fn expand_home(path: &str) -> Result<PathBuf> {
+    match path.strip_prefix('~') {
+        Some("") => home_dir(),
+        Some(rest) if rest.starts_with(['/', '\\']) => Ok(home_dir()?.join(&rest[1..])),
+        _ => Ok(PathBuf::from(path)),
+    }
+}
+
+fn xdg_dir(var: &str, fallback: &str) -> Result<PathBuf> {
+    match env::var_os(var).map(PathBuf::from) {
+        Some(dir) if dir.is_absolute() => Ok(dir.join(APP_DIR)),
+        _ => Ok(home_dir()?.join(fallback).join(APP_DIR)),
+    }
+}
+
+pub fn config_dir() -> Result<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config")
+}
+
+pub fn data_dir() -> Result<PathBuf> {
+    xdg_dir("XDG_DATA_HOME", ".local/share")
+}
+
+pub fn state_dir() -> Result<PathBuf> {
+    xdg_dir("XDG_STATE_HOME", ".local/state")
+}
+
+// XDG_RUNTIME_DIR has no default in the spec, state directory is private enough instead
+pub fn runtime_dir() -> Result<PathBuf> {
+    match env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from) {
+        Some(dir) if dir.is_absolute() => Ok(dir.join(APP_DIR)),
+        _ => state_dir(),
+    }
+}
+
+// Files used by a session =================================
+pub struct Paths {
+    pub config_file: PathBuf,
+    pub state_file: PathBuf,
+    // commands typed after `:`, kept next to the state
+    pub history_file: PathBuf,
+    // control socket of the running instance
+    pub socket_file: PathBuf,
+    // state file from old versions, moved by migrate when the default place is used
+    legacy_state_file: Option<PathBuf>,
+}
+
+impl Paths {
+    // overrides come from --config and --state
+    pub fn new(config_file: Option<PathBuf>, state_file: Option<PathBuf>) -> Result<Self> {
+        let config_file = match config_file {
+            Some(path) => path,
+            None => config_dir()?.join(CONFIG_FILE),
+        };
+
+        // old versions kept state right in the home directory
+        let (state_file, legacy_state_file) = match state_file {
+            Some(path) => (path, None),
+            None => (
+                state_dir()?.join(STATE_FILE),
+                Some(home_dir()?.join(STATE_FILE)),
+            ),
+        };
+
+        Ok(Paths {
+            config_file,
+            history_file: state_file.with_file_name(HISTORY_FILE),
+            socket_file: runtime_dir()?.join(SOCKET_FILE),
+            state_file,
+            legacy_state_file,
+        })
+    }
+
+    // only when the player starts, ctl and --check-config leave user files alone
+    pub fn migrate(&self) -> Result<()> {
+        match &self.legacy_state_file {
+            Some(old) => migrate(old, &self.state_file),
+            None => Ok(()),
+        }
+    }
+}
+
+// moves file and its legacy .stats companion unless the new location is already used
+fn migrate(old: &Path, new: &Path) -> Result<()> {
+    if !old.exists() || new.exists() {
+        return Ok(());
+    }
+    if let Some(dir) = new.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    move_file(old, new)?;
+    let old_stats = old.with_extension("stats");
+    if old_stats.exists() {
+        move_file(&old_stats, &new.with_extension("stats"))?;
+    }
+    println!("Moved {} to {}", old.display(), new.display());
+
+    Ok(())
+}
+
+// rename doesn't work across filesystems
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    if fs::rename(from, to).is_err() {
+        fs::copy(from, to)?;
+        fs::remove_file(from)?;
+    }
+
+    Ok(())
+}
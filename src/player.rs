@@ -1,22 +1,29 @@
 use std::{
-    collections::VecDeque,
+    collections::{HashMap, VecDeque},
     fs::File,
+    hash::{BuildHasher, RandomState},
     io::BufReader,
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::{Arc, RwLock},
     thread,
     time::Duration,
 };
 
 use anyhow::{Ok, Result};
-use rodio::{source, Decoder, OutputStream, Sink, Source};
+use rodio::{Decoder, OutputStream, Sink, Source};
+
+use crate::library::{self, TrackInfo};
 
 pub struct Player {
     pub now_playing: String,
     pub queue: VecDeque<PathBuf>,
     pub song_index: usize,
     pub repeat: bool,
+    pub play_counts: HashMap<PathBuf, usize>,
     restart: bool,
+    missing_in_row: usize,
+    // track, position and paused state saved on exit, applied when that track starts
+    resume_at: Option<(PathBuf, Duration, bool)>,
     sink: Sink,
     total_duration: usize,
 }
@@ -34,7 +41,10 @@ impl Player {
                 queue: VecDeque::new(),
                 song_index: 0,
                 repeat: false,
+                play_counts: HashMap::new(),
                 restart: false,
+                missing_in_row: 0,
+                resume_at: None,
                 total_duration: 0,
             },
         ))
@@ -44,6 +54,14 @@ impl Player {
         self.sink.get_pos().as_secs() as usize
     }
 
+    pub fn get_position(&self) -> Duration {
+        self.sink.get_pos()
+    }
+
+    pub fn resume_from(&mut self, track: PathBuf, position: Duration, paused: bool) {
+        self.resume_at = Some((track, position, paused));
+    }
+
     pub fn get_duration_total(&self) -> usize {
         self.total_duration
     }
@@ -120,6 +138,13 @@ impl Player {
         self.sink.try_seek(new).unwrap_or(());
     }
 
+    // past the end the track just finishes
+    pub fn seek(&self, position: u64) {
+        self.sink
+            .try_seek(Duration::from_secs(position))
+            .unwrap_or(());
+    }
+
     pub fn is_paused(&self) -> bool {
         self.sink.is_paused()
     }
@@ -137,6 +162,170 @@ impl Player {
     pub fn restart(&mut self) {
         self.restart = true;
     }
+
+    // the player thread starts the next track once the sink is empty,
+    // so it is cleared once and song_index jumps over the tracks in between
+    pub fn skip(&mut self, count: usize) {
+        self.song_index = skip_index(self.song_index, count, self.queue.len());
+        self.clear();
+    }
+
+    // index of the track being played, song_index already points at the next one
+    pub fn current(&self) -> Option<usize> {
+        if self.now_playing.is_empty() {
+            return None;
+        }
+        self.song_index.checked_sub(1)
+    }
+
+    // Queue editing =======================================
+    // song_index points at the track after the current one, all edits keep it that way
+
+    // queue[i] becomes old queue[order[i]]
+    pub fn reorder(&mut self, order: Vec<usize>) {
+        let old_queue = std::mem::take(&mut self.queue);
+        self.queue = order.iter().map(|&i| old_queue[i].clone()).collect();
+
+        if self.song_index != 0
+            && let Some(current) = order.iter().position(|&i| i == self.song_index - 1)
+        {
+            self.song_index = current + 1;
+        }
+    }
+
+    // replaces the whole queue, the current track keeps its place if it is still there
+    pub fn set_queue(&mut self, queue: VecDeque<PathBuf>) {
+        let current = self
+            .song_index
+            .checked_sub(1)
+            .and_then(|i| self.queue.get(i))
+            .cloned();
+
+        self.song_index = match current.and_then(|x| queue.iter().position(|path| *path == x)) {
+            Some(index) => index + 1,
+            None => self.song_index.min(queue.len()),
+        };
+        self.queue = queue;
+    }
+
+    // new queue played from its first track, like an opened playlist
+    pub fn play_queue(&mut self, queue: VecDeque<PathBuf>) {
+        self.song_index = 0;
+        self.resume_at = None;
+        self.clear();
+        self.queue = queue;
+    }
+
+    // returns new position of the track
+    pub fn move_track(&mut self, index: usize, up: bool) -> usize {
+        if index >= self.queue.len() {
+            return index;
+        }
+
+        let new_index = if up {
+            index.saturating_sub(1)
+        } else {
+            (index + 1).min(self.queue.len() - 1)
+        };
+        let mut order: Vec<usize> = (0..self.queue.len()).collect();
+        order.swap(index, new_index);
+        self.reorder(order);
+
+        new_index
+    }
+
+    pub fn move_to_top(&mut self, index: usize) {
+        if index >= self.queue.len() {
+            return;
+        }
+
+        let mut order: Vec<usize> = (0..self.queue.len()).collect();
+        order.remove(index);
+        order.insert(0, index);
+        self.reorder(order);
+    }
+
+    // inserted right after the current track, in the given order
+    pub fn play_next(&mut self, paths: Vec<PathBuf>) {
+        let index = self.song_index.min(self.queue.len());
+        for (offset, path) in paths.into_iter().enumerate() {
+            self.queue.insert(index + offset, path);
+        }
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        if index >= self.queue.len() {
+            return;
+        }
+
+        self.queue.remove(index);
+        if index < self.song_index {
+            self.song_index -= 1;
+        }
+    }
+}
+
+// Fisher-Yates, keys of std's hasher are random for every process
+// which is plenty for a queue
+pub fn shuffle<T>(items: &mut [T]) {
+    let random = RandomState::new();
+    for i in (1..items.len()).rev() {
+        let j = (random.hash_one(i) % (i as u64 + 1)) as usize;
+        items.swap(i, j);
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum SortKey {
+    Path,
+    Tag,
+    TrackNumber,
+}
+
+// tracks without a tag go after the tagged ones
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum SortField {
+    Number(u32),
+    Text(String),
+    Missing,
+}
+
+impl SortKey {
+    // order for Player::reorder, stable so tracks with equal keys keep their order
+    pub fn order(&self, queue: &VecDeque<PathBuf>) -> Vec<usize> {
+        let keys: Vec<Vec<SortField>> = queue.iter().map(|path| self.fields(path)).collect();
+
+        let mut order: Vec<usize> = (0..queue.len()).collect();
+        order.sort_by(|&a, &b| {
+            keys[a]
+                .cmp(&keys[b])
+                .then_with(|| library::natural_cmp_paths(&queue[a], &queue[b]))
+        });
+
+        order
+    }
+
+    fn fields(&self, path: &Path) -> Vec<SortField> {
+        let text = |x: Option<String>| {
+            x.map(|x| SortField::Text(x.to_lowercase()))
+                .unwrap_or(SortField::Missing)
+        };
+        let number = |x: Option<u32>| x.map(SortField::Number).unwrap_or(SortField::Missing);
+
+        match self {
+            SortKey::Path => vec![],
+            SortKey::Tag => {
+                let info = TrackInfo::read(path);
+                vec![
+                    text(info.artist),
+                    text(info.album),
+                    number(info.track_number),
+                    text(info.title),
+                ]
+            }
+            SortKey::TrackNumber => vec![number(TrackInfo::read(path).track_number)],
+        }
+    }
 }
 
 pub fn main_loop(player: Arc<RwLock<Player>>) {
@@ -159,7 +348,10 @@ pub fn main_loop(player: Arc<RwLock<Player>>) {
         drop(mutex);
         let mut mutex = player.write().unwrap();
 
-        if mutex.repeat {
+        // a new queue starts at 0 and a restored one at the saved track,
+        // there is no previous track to repeat yet
+        let resuming = mutex.resume_at.is_some();
+        if mutex.repeat && mutex.song_index > 0 && !resuming {
             mutex.song_index -= 1;
         }
         if mutex.song_index > mutex.queue.len() - 1 {
@@ -170,14 +362,68 @@ pub fn main_loop(player: Arc<RwLock<Player>>) {
 
         mutex.restart = false;
 
+        // missing tracks stay in queue so they can be relinked later
         if !track_path.exists() {
-            let i = mutex.song_index - 1;
-            mutex.queue.remove(i);
+            mutex.missing_in_row += 1;
+            if mutex.missing_in_row >= mutex.queue.len() {
+                mutex.missing_in_row = 0;
+                drop(mutex);
+                thread::sleep(Duration::from_millis(1000));
+            }
             continue;
         }
+        mutex.missing_in_row = 0;
 
-        if let Err(err) = mutex.play(track_path) {
+        if let Err(err) = mutex.play(track_path.clone()) {
             panic!("Error while decoding file, try MPEG-4 codec");
         }
+        *mutex.play_counts.entry(track_path.clone()).or_insert(0) += 1;
+
+        if let Some((track, position, paused)) = mutex.resume_at.take()
+            && track == track_path
+        {
+            if paused {
+                mutex.pause();
+            }
+            mutex.sink.try_seek(position).unwrap_or(());
+        }
+    }
+}
+
+// song_index after skipping count tracks, the queue starts over after the last one
+fn skip_index(song_index: usize, count: usize, len: usize) -> usize {
+    if len == 0 || count == 0 {
+        return song_index;
+    }
+    (song_index + count - 1) % len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skip_one_keeps_next_track() {
+        assert_eq!(skip_index(1, 1, 5), 1);
+        assert_eq!(skip_index(4, 1, 5), 4);
+    }
+
+    #[test]
+    fn skip_count_passes_over_tracks() {
+        // playing queue[0], 3 skips land on queue[3]
+        assert_eq!(skip_index(1, 3, 5), 3);
+        assert_eq!(skip_index(2, 2, 5), 3);
+    }
+
+    #[test]
+    fn skip_wraps_around_queue() {
+        assert_eq!(skip_index(5, 1, 5), 0);
+        assert_eq!(skip_index(4, 3, 5), 1);
+        assert_eq!(skip_index(1, 11, 5), 1);
+    }
+
+    #[test]
+    fn skip_empty_queue() {
+        assert_eq!(skip_index(0, 3, 0), 0);
     }
 }
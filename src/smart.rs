@@ -0,0 +1,208 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use toml::Value;
+
+use crate::library::{self, TrackInfo};
+
+pub const SMART_PLAYLIST_FILE_EXT: &str = "smart";
+
+// Smart playlist file =====================================
+// match = "all"
+// rules = [
+//     { field = "genre", op = "=", value = "jazz" },
+//     { field = "year", op = "<", value = 1970 },
+// ]
+#[derive(Debug, Deserialize)]
+pub struct SmartPlaylist {
+    #[serde(default, rename = "match")]
+    pub match_mode: MatchMode,
+    #[serde(default)]
+    pub root: Option<PathBuf>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Debug, Deserialize, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchMode {
+    #[default]
+    All,
+    Any,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Rule {
+    pub field: String,
+    pub op: String,
+    pub value: Value,
+}
+
+impl SmartPlaylist {
+    pub fn open(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let playlist: SmartPlaylist = toml::de::from_str(&content)
+            .map_err(|e| anyhow!("Error occured while parsing smart playlist:\n{e}"))?;
+
+        if playlist.limit == Some(0) {
+            return Err(anyhow!("Smart playlist \"limit\" must be at least 1"));
+        }
+        for rule in &playlist.rules {
+            rule.check()?;
+        }
+
+        Ok(playlist)
+    }
+
+    // library is scanned again every time, so new files show up right away,
+    // tags come from the cache unless the file changed
+    pub fn evaluate(
+        &self,
+        library_folder: Option<&Path>,
+        play_counts: &HashMap<PathBuf, usize>,
+    ) -> Result<VecDeque<PathBuf>> {
+        let root = self.root.as_deref().or(library_folder).ok_or_else(|| {
+            anyhow!("Set \"library_folder\" in preferences or \"root\" in smart playlist")
+        })?;
+        if !root.exists() {
+            return Err(anyhow!("Library folder {} doesn't exist", root.display()));
+        }
+
+        let needs_tags = self.rules.iter().any(|rule| rule.needs_tags());
+        let mut result = VecDeque::new();
+
+        for path in library::scan(root) {
+            let info = if needs_tags {
+                TrackInfo::read_cached(&path)
+            } else {
+                TrackInfo::default()
+            };
+            let mut results = self
+                .rules
+                .iter()
+                .map(|rule| rule.matches(&path, &info, play_counts));
+
+            let matched = match self.match_mode {
+                MatchMode::All => results.all(|x| x),
+                MatchMode::Any => results.any(|x| x),
+            };
+            if matched {
+                result.push_back(path);
+            }
+
+            if self.limit.is_some_and(|limit| result.len() >= limit) {
+                break;
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+// Rules ===================================================
+const STRING_FIELDS: [&str; 6] = ["title", "artist", "album", "genre", "path", "extension"];
+const NUMBER_FIELDS: [&str; 5] = ["year", "track", "duration", "added", "play_count"];
+const OPERATORS: [&str; 7] = ["=", "!=", "<", "<=", ">", ">=", "contains"];
+
+impl Rule {
+    fn check(&self) -> Result<()> {
+        if !STRING_FIELDS.contains(&self.field.as_str())
+            && !NUMBER_FIELDS.contains(&self.field.as_str())
+        {
+            return Err(anyhow!("Unknown smart playlist field \"{}\"", self.field));
+        }
+        if !OPERATORS.contains(&self.op.as_str()) {
+            return Err(anyhow!("Unknown smart playlist operator \"{}\"", self.op));
+        }
+        if NUMBER_FIELDS.contains(&self.field.as_str()) {
+            if self.value.as_integer().is_none() {
+                return Err(anyhow!("Field \"{}\" expects a number", self.field));
+            }
+            if self.op == "contains" {
+                return Err(anyhow!(
+                    "Operator \"contains\" only works with text fields, not \"{}\"",
+                    self.field
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn needs_tags(&self) -> bool {
+        matches!(
+            self.field.as_str(),
+            "title" | "artist" | "album" | "genre" | "year" | "track" | "duration"
+        )
+    }
+
+    fn matches(
+        &self,
+        path: &Path,
+        info: &TrackInfo,
+        play_counts: &HashMap<PathBuf, usize>,
+    ) -> bool {
+        let number = match self.field.as_str() {
+            "year" => Some(info.year.map(|x| x as i64)),
+            "track" => Some(info.track_number.map(|x| x as i64)),
+            "duration" => Some(info.duration.map(|x| x as i64)),
+            "added" => Some(library::days_since_added(path).map(|x| x as i64)),
+            "play_count" => Some(Some(play_counts.get(path).copied().unwrap_or(0) as i64)),
+            _ => None,
+        };
+
+        if let Some(number) = number {
+            // track without the tag never matches
+            let (Some(left), Some(right)) = (number, self.value.as_integer()) else {
+                return false;
+            };
+            return match self.op.as_str() {
+                "=" => left == right,
+                "!=" => left != right,
+                "<" => left < right,
+                "<=" => left <= right,
+                ">" => left > right,
+                ">=" => left >= right,
+                _ => false,
+            };
+        }
+
+        let string = match self.field.as_str() {
+            "title" => info.title.clone(),
+            "artist" => info.artist.clone(),
+            "album" => info.album.clone(),
+            "genre" => info.genre.clone(),
+            "path" => path.to_str().map(|x| x.to_string()),
+            "extension" => path
+                .extension()
+                .and_then(|x| x.to_str())
+                .map(|x| x.to_string()),
+            _ => None,
+        };
+        let Some(left) = string.map(|x| x.to_lowercase()) else {
+            return false;
+        };
+        let right = match &self.value {
+            Value::String(x) => x.to_lowercase(),
+            other => other.to_string(),
+        };
+
+        match self.op.as_str() {
+            "=" => left == right,
+            "!=" => left != right,
+            "<" => left < right,
+            "<=" => left <= right,
+            ">" => left > right,
+            ">=" => left >= right,
+            "contains" => left.contains(&right),
+            _ => false,
+        }
+    }
+}
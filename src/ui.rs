@@ -6,16 +6,20 @@ use std::{
 
 use anyhow::{anyhow, Result};
 use ratatui::{
-    layout::{Alignment, Constraint, Direction, Flex, Layout, Rect},
-    style::{Color, Style},
+    layout::{Alignment, Constraint, Direction, Flex, Layout},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
     widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph},
     DefaultTerminal,
 };
 
 use crate::{
     actions::MUSIC_EXTENSIONS,
+    formats::{M3U8_FILE_EXT, M3U_FILE_EXT, PLS_FILE_EXT, XSPF_FILE_EXT},
+    keymap::HelpLine,
     player::Player,
-    workspace::{TreeState, Windows, Workspace, PLAYLIST_FILE_EXT},
+    smart::SMART_PLAYLIST_FILE_EXT,
+    workspace::{Areas, PlaylistKind, TreeState, Windows, Workspace, PLAYLIST_FILE_EXT},
 };
 
 pub struct UI;
@@ -26,29 +30,28 @@ impl UI {
         player: Arc<RwLock<Player>>,
         mut terminal: DefaultTerminal,
     ) -> Result<()> {
-        let config = {
+        let mut theme = {
             let ctx = workspace.read().unwrap();
-            ctx.config.clone()
+            ctx.config
+                .themes
+                .get(&ctx.config.selected_theme)
+                .ok_or_else(|| anyhow!("Theme name in config invalid"))?
+                .clone()
         };
-        let mut selected_theme = config.selected_theme;
-        let mut theme = config
-            .themes
-            .get(&selected_theme)
-            .ok_or_else(|| anyhow!("Theme name in config invalid"))?
-            .clone();
         let mut list_state = ListState::default();
 
         loop {
+            let mut areas = Areas::default();
+
             let ctx = workspace.read().unwrap();
             if !ctx.running {
                 break;
             }
             let player_mutex = player.read().unwrap();
 
-            if ctx.config.selected_theme != selected_theme {
-                selected_theme = ctx.config.selected_theme.clone();
-
-                theme = ctx.config.themes.get(&selected_theme).unwrap().clone();
+            // SelectTheme and ReloadConfig both change it
+            if let Some(selected_theme) = ctx.config.themes.get(&ctx.config.selected_theme) {
+                theme = selected_theme.clone();
             }
 
             terminal.draw(|frame| {
@@ -108,6 +111,10 @@ impl UI {
                     theme.progress_bar_elapsed[1],
                     theme.progress_bar_elapsed[2],
                 ));
+                let marked = theme.marked.unwrap_or(theme.progress_bar_elapsed);
+                let marked_style = Style::default()
+                    .fg(Color::Rgb(marked[0], marked[1], marked[2]))
+                    .add_modifier(Modifier::BOLD);
 
                 // player block ============================================
                 let player_block = Block::default()
@@ -130,17 +137,26 @@ impl UI {
                     .split(player_block_area);
 
                 // tree ===================================================
-                let tree_state = Some(ctx.tree.state.clone())
-                    .map(|x| {
-                        if x == TreeState::Files {
-                            "Files"
-                        } else if x == TreeState::Queue {
-                            "Queue"
-                        } else {
-                            "Playlists"
-                        }
-                    })
-                    .unwrap();
+                let tree_state = match (&ctx.tree.state, &ctx.editor) {
+                    (TreeState::Files, _) => "Files".to_string(),
+                    (TreeState::Queue, _) => "Queue".to_string(),
+                    (TreeState::Playlists, _) => "Playlists".to_string(),
+                    (TreeState::Editor, Some(editor)) => format!(
+                        "{}{} [{}]",
+                        editor.playlist.name,
+                        if editor.modified { "*" } else { "" },
+                        format_time(editor.total_duration())
+                    ),
+                    (TreeState::Editor, None) => "Editor".to_string(),
+                };
+                // visual mode and number of marked rows
+                let tree_state = if ctx.tree.visual_start.is_some() {
+                    format!("{} -- VISUAL --", tree_state)
+                } else if !ctx.tree.marked.is_empty() {
+                    format!("{} ({} marked)", tree_state, ctx.tree.marked.len())
+                } else {
+                    tree_state
+                };
                 let tree_block = Block::default()
                     .title(tree_state)
                     .borders(Borders::ALL)
@@ -153,36 +169,37 @@ impl UI {
                 } else {
                     '\u{f0456}'
                 };
-                let metadata_str = format!(
+                let mut metadata_str = format!(
                     "Now playing: {}\nVolume: {}%\nRepeat: {}\n",
                     player_mutex.now_playing.clone(),
                     player_mutex.get_volume_percantage(),
                     repeat_icon,
                 );
+
+                // selected playlist metadata
+                if ctx.tree.state == TreeState::Playlists
+                    && let Some(playlist) = ctx.tree.playlists.get(ctx.tree.selected)
+                    && let Some(info) = &playlist.info
+                {
+                    metadata_str += &format!(
+                        "\n{}\nCreated: {}\nModified: {}\n",
+                        info.description,
+                        info.created.date.map(|x| x.to_string()).unwrap_or_default(),
+                        info.modified
+                            .date
+                            .map(|x| x.to_string())
+                            .unwrap_or_default(),
+                    );
+                }
                 let song_metadata = Paragraph::new(metadata_str)
                     .style(text_style)
                     .alignment(Alignment::Center);
 
-                // time formatting ===========================================
-                let current_mins = player_mutex.get_duration_current() / 60;
-                let current_secs = player_mutex.get_duration_current() - 60 * current_mins;
-                let current_secs = if current_secs < 10 {
-                    "0".to_string() + &current_secs.to_string()
-                } else {
-                    current_secs.to_string()
-                };
-                let full_mins = player_mutex.get_duration_total() / 60;
-                let full_secs = player_mutex.get_duration_total() - 60 * full_mins;
-                let full_secs = if full_secs < 10 {
-                    "0".to_string() + &full_secs.to_string()
-                } else {
-                    full_secs.to_string()
-                };
-
                 // statusbar time and block ==============================================
                 let statusbar_str = format!(
-                    " {}:{} / {}:{} ",
-                    current_mins, current_secs, full_mins, full_secs
+                    " {} / {} ",
+                    format_time(player_mutex.get_duration_current()),
+                    format_time(player_mutex.get_duration_total())
                 );
 
                 let pause_icon = if player_mutex.is_paused() {
@@ -190,7 +207,7 @@ impl UI {
                 } else {
                     " \u{f040a} "
                 };
-                let statusbar = Block::default()
+                let mut statusbar = Block::default()
                     .title(statusbar_str)
                     .title_bottom(pause_icon)
                     .borders(Borders::ALL)
@@ -198,6 +215,13 @@ impl UI {
                     .title_style(title_style)
                     .title_alignment(Alignment::Center);
 
+                // count and keys of an unfinished sequence like "5g"
+                if !ctx.pending_keys.is_empty() {
+                    statusbar = statusbar.title_bottom(
+                        Line::from(format!(" {} ", ctx.pending_keys)).right_aligned(),
+                    );
+                }
+
                 // status bar poloska =============================================
                 let percantage = if player_mutex.get_duration_total() != 0 {
                     (player_block_area.width as usize).saturating_sub(2) as f32
@@ -227,9 +251,20 @@ impl UI {
                                 if MUSIC_EXTENSIONS.contains(&x) {
                                     let formatted = format!("{} {}", '\u{f0387}', path_str);
                                     return ListItem::new(formatted).style(music_file_style);
-                                } else if x == PLAYLIST_FILE_EXT {
+                                } else if [
+                                    PLAYLIST_FILE_EXT,
+                                    M3U_FILE_EXT,
+                                    M3U8_FILE_EXT,
+                                    XSPF_FILE_EXT,
+                                    PLS_FILE_EXT,
+                                ]
+                                .contains(&x)
+                                {
                                     let formatted = format!("{} {}", '\u{f0cb8}', path_str);
                                     return ListItem::new(formatted).style(progress_style);
+                                } else if x == SMART_PLAYLIST_FILE_EXT {
+                                    let formatted = format!("{} {}", '\u{f0df2}', path_str);
+                                    return ListItem::new(formatted).style(progress_style);
                                 }
                             }
                             ListItem::new(path_str)
@@ -250,15 +285,72 @@ impl UI {
                             ListItem::new(path.file_name().unwrap().to_str().unwrap())
                         })
                         .collect();
+                } else if ctx.tree.state == TreeState::Editor {
+                    // title on the left, duration on the right
+                    let width = layout[0].width.saturating_sub(2) as usize;
+                    list_items = ctx
+                        .editor
+                        .iter()
+                        .flat_map(|editor| editor.tracks.iter())
+                        .map(|track| {
+                            let title = track.title.clone().unwrap_or_else(|| {
+                                track
+                                    .path
+                                    .file_name()
+                                    .unwrap()
+                                    .to_str()
+                                    .unwrap()
+                                    .to_string()
+                            });
+                            let duration = track.duration.map(format_time).unwrap_or_default();
+                            let title: String = title
+                                .chars()
+                                .take(width.saturating_sub(duration.len() + 1))
+                                .collect();
+                            let padding =
+                                width.saturating_sub(title.chars().count() + duration.len());
+
+                            ListItem::new(format!("{}{}{}", title, " ".repeat(padding), duration))
+                        })
+                        .collect();
                 } else {
                     list_items = ctx
                         .tree
                         .playlists
                         .iter()
-                        .map(|name| ListItem::new(name.clone()).style(progress_style))
+                        .map(|playlist| {
+                            let icon = match playlist.kind {
+                                PlaylistKind::Smart => '\u{f0df2}',
+                                _ => '\u{f0cb8}',
+                            };
+                            // an exported copy has the same name as its source
+                            let label = match playlist.kind {
+                                PlaylistKind::Native | PlaylistKind::Smart => String::new(),
+                                _ => playlist
+                                    .path
+                                    .extension()
+                                    .map(|x| format!(" [{}]", x.to_string_lossy()))
+                                    .unwrap_or_default(),
+                            };
+                            ListItem::new(format!("{} {}{}", icon, playlist.name, label))
+                                .style(progress_style)
+                        })
                         .collect();
                 }
 
+                // marked rows ===================================================
+                let list_items: Vec<ListItem> = list_items
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, item)| {
+                        if ctx.tree.is_marked(index) {
+                            item.style(marked_style)
+                        } else {
+                            item
+                        }
+                    })
+                    .collect();
+
                 // list itself ===============================================
                 let list = List::new(list_items)
                     .block(tree_block)
@@ -285,7 +377,7 @@ impl UI {
                 // theme select window list items
                 let mut themes_list_items: Vec<ListItem> = vec![];
                 if ctx.window == Windows::ThemeSelect {
-                    let mut sorted_names: Vec<&String> = config.themes.keys().collect();
+                    let mut sorted_names: Vec<&String> = ctx.config.themes.keys().collect();
                     sorted_names.sort();
                     themes_list_items = sorted_names
                         .iter()
@@ -308,6 +400,15 @@ impl UI {
                         .style(error_style)
                         .alignment(Alignment::Center);
                 }
+
+                // progress notice, same place as error popup
+                let mut notice_paragraph = Paragraph::default();
+                if let Windows::Notice(text) | Windows::Confirm(text) = &ctx.window {
+                    notice_paragraph = Paragraph::new(text.to_string())
+                        .block(Block::default().borders(Borders::ALL).style(block_style))
+                        .style(text_style)
+                        .alignment(Alignment::Center);
+                }
                 let layout_error_horizontal = Layout::default()
                     .direction(Direction::Horizontal)
                     .flex(Flex::Center)
@@ -319,17 +420,116 @@ impl UI {
                     .constraints([Constraint::Percentage(50)])
                     .split(layout_error_horizontal[0]);
 
+                // report popup, scrollable list of lines
+                let mut report_list_items: Vec<ListItem> = vec![];
+                let mut report_title = String::new();
+                if let Windows::Report(title, lines) = &ctx.window {
+                    report_title = title.clone();
+                    report_list_items = lines
+                        .iter()
+                        .map(|line| ListItem::new(line.to_string()))
+                        .collect();
+                }
+                let report_list = List::new(report_list_items)
+                    .block(
+                        Block::default()
+                            .title(report_title)
+                            .title_style(title_style)
+                            .style(block_style)
+                            .borders(Borders::ALL),
+                    )
+                    .style(text_style)
+                    .highlight_style(highlighted_style);
+                let layout_report_horizontal = Layout::default()
+                    .direction(Direction::Horizontal)
+                    .flex(Flex::Center)
+                    .constraints([Constraint::Percentage(70)])
+                    .split(size);
+                let layout_report = Layout::default()
+                    .direction(Direction::Vertical)
+                    .flex(Flex::Center)
+                    .constraints([Constraint::Percentage(80)])
+                    .split(layout_report_horizontal[0]);
+
+                // help popup, bindings of the live keymap
+                let mut help_list_items: Vec<ListItem> = vec![];
+                let mut help_title = String::new();
+                if let Windows::Help(context) = ctx.window {
+                    help_title = format!(
+                        " Help: {} | filter: {}\u{258f}",
+                        context.name(),
+                        ctx.stdin_buffer
+                    );
+                    help_list_items = ctx
+                        .config
+                        .keymap_local
+                        .help(context, &ctx.stdin_buffer)
+                        .into_iter()
+                        .map(|line| match line {
+                            HelpLine::Section(name) => ListItem::new(name).style(title_style),
+                            HelpLine::Binding(keys, action) => {
+                                ListItem::new(format!("  {:<16} {}", keys, action))
+                            }
+                        })
+                        .collect();
+                }
+                let help_list = List::new(help_list_items)
+                    .block(
+                        Block::default()
+                            .title(help_title)
+                            .title_style(title_style)
+                            .style(block_style)
+                            .borders(Borders::ALL),
+                    )
+                    .style(text_style)
+                    .highlight_style(highlighted_style);
+
+                let layout_notice = Layout::default()
+                    .direction(Direction::Vertical)
+                    .flex(Flex::Center)
+                    .constraints([Constraint::Length(4)])
+                    .split(layout_error_horizontal[0]);
+
                 // save playlist input
-                let save_playlist_block = Block::default()
-                    .title("Enter playlist name")
+                let save_playlist_title = match ctx.window {
+                    Windows::PlaylistRename => "Rename playlist to",
+                    Windows::PlaylistDuplicate => "Name of the copy",
+                    Windows::RelinkPrefix => "Relink: old prefix -> new prefix",
+                    Windows::Command => "Command",
+                    _ => "Enter playlist name",
+                };
+                // paths need more room than names
+                let save_playlist_width =
+                    if matches!(ctx.window, Windows::RelinkPrefix | Windows::Command) {
+                        Constraint::Percentage(60)
+                    } else {
+                        Constraint::Length(32)
+                    };
+                let mut save_playlist_block = Block::default()
+                    .title(save_playlist_title)
                     .title_style(title_style)
                     .title_alignment(Alignment::Center)
                     .borders(Borders::ALL)
                     .style(block_style);
+
+                // tab completion candidates, the chosen one highlighted
+                if ctx.window == Windows::Command && !ctx.command.completions.is_empty() {
+                    let mut spans = vec![];
+                    for (index, completion) in ctx.command.completions.iter().enumerate() {
+                        let style = if ctx.command.completion == Some(index) {
+                            highlighted_style
+                        } else {
+                            text_style
+                        };
+                        spans.push(Span::styled(format!(" {} ", completion.label), style));
+                    }
+                    save_playlist_block =
+                        save_playlist_block.title_bottom(Line::from(spans).left_aligned());
+                }
                 let layout_save_playlist_horizontal = Layout::default()
                     .direction(Direction::Horizontal)
                     .flex(Flex::Center)
-                    .constraints([Constraint::Length(32)])
+                    .constraints([save_playlist_width])
                     .split(size);
                 let layout_save_playlist = Layout::default()
                     .direction(Direction::Vertical)
@@ -338,9 +538,15 @@ impl UI {
                     .split(layout_save_playlist_horizontal[0]);
 
                 // save playlist input text
-                let save_playlist_widget = Paragraph::new(ctx.stdin_buffer.clone() + "\u{258f}")
-                    .block(save_playlist_block)
-                    .style(text_style);
+                let prompt = if ctx.window == Windows::Command {
+                    ":"
+                } else {
+                    ""
+                };
+                let save_playlist_widget =
+                    Paragraph::new(format!("{}{}\u{258f}", prompt, ctx.stdin_buffer))
+                        .block(save_playlist_block)
+                        .style(text_style);
 
                 // drawing what is needed
                 list_state.select(Some(ctx.tree.selected));
@@ -353,6 +559,11 @@ impl UI {
                 frame.render_widget(player_block, layout[1]);
                 frame.render_widget(song_metadata, layout_player[0]);
                 frame.render_widget(statusbar_progress, layout_player[1]);
+                areas = Areas {
+                    list: layout[0],
+                    list_offset: list_state.offset(),
+                    progress: layout_player[1],
+                };
 
                 if ctx.window == Windows::ThemeSelect {
                     frame.render_widget(Clear, layout_themes_block[0]);
@@ -361,10 +572,34 @@ impl UI {
                         layout_themes_block[0],
                         &mut list_state,
                     );
+                } else if let Windows::Report(..) = ctx.window {
+                    frame.render_widget(Clear, layout_report[0]);
+                    frame.render_stateful_widget(
+                        report_list,
+                        layout_report[0],
+                        &mut ListState::default().with_selected(Some(ctx.popup_selected)),
+                    );
+                } else if let Windows::Help(_) = ctx.window {
+                    frame.render_widget(Clear, layout_report[0]);
+                    frame.render_stateful_widget(
+                        help_list,
+                        layout_report[0],
+                        &mut ListState::default().with_selected(Some(ctx.popup_selected)),
+                    );
                 } else if let Windows::Error(_) = ctx.window {
                     frame.render_widget(Clear, layout_error[0]);
                     frame.render_widget(error_paragraph, layout_error[0]);
-                } else if ctx.window == Windows::PlaylistSave {
+                } else if let Windows::Notice(_) | Windows::Confirm(_) = ctx.window {
+                    frame.render_widget(Clear, layout_notice[0]);
+                    frame.render_widget(notice_paragraph, layout_notice[0]);
+                } else if matches!(
+                    ctx.window,
+                    Windows::PlaylistSave
+                        | Windows::PlaylistRename
+                        | Windows::PlaylistDuplicate
+                        | Windows::RelinkPrefix
+                        | Windows::Command
+                ) {
                     frame.render_widget(Clear, layout_save_playlist[0]);
                     frame.render_widget(save_playlist_widget, layout_save_playlist[0]);
                 }
@@ -372,9 +607,25 @@ impl UI {
 
             drop(ctx);
             drop(player_mutex);
+
+            // mouse clicks are matched against the last frame
+            if workspace.read().unwrap().areas != areas {
+                workspace.write().unwrap().areas = areas;
+            }
             thread::sleep(Duration::from_millis(32));
         }
 
         Ok(())
     }
 }
+
+// 75 -> 1:15, 3725 -> 1:02:05
+fn format_time(secs: usize) -> String {
+    let (hours, mins, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+
+    if hours != 0 {
+        format!("{}:{:02}:{:02}", hours, mins, secs)
+    } else {
+        format!("{}:{:02}", mins, secs)
+    }
+}
@@ -0,0 +1,857 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    marker::PhantomData,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use crossterm::event::{KeyEvent, KeyModifiers};
+use serde::{
+    de::{
+        value::MapAccessDeserializer, DeserializeSeed, Deserializer, IntoDeserializer, MapAccess,
+        Visitor,
+    },
+    Deserialize,
+};
+use serde_spanned::__unstable::{END_FIELD, NAME, START_FIELD, VALUE_FIELD};
+use toml::Value;
+
+use crate::{
+    actions::{action_names, arg_action_names},
+    formats::PlaylistFormat,
+    keymap::{
+        key_names, modifier_names, parse_key, parse_keys, parse_modifier, Context, CONTEXT_NAMES,
+    },
+};
+
+const TABLES: [&str; 4] = ["preferences", "keymaps", "themes", "queue_filter"];
+const PREFERENCES: [&str; 8] = [
+    "selected_theme",
+    "playlists_folder",
+    "library_folder",
+    "playlist_format",
+    "auto_resume",
+    "autosave_interval",
+    "watch_config",
+    "key_timeout",
+];
+const QUEUE_FILTER: [&str; 3] = ["extensions", "min_duration", "pattern"];
+const THEME_COLOURS: [&str; 9] = [
+    "text_headline",
+    "background",
+    "border",
+    "music",
+    "progress_bar_elapsed",
+    "highlighted",
+    "directories",
+    "error",
+    "error_text",
+];
+// "text" falls back to black, "marked" to progress_bar_elapsed
+const OPTIONAL_THEME_COLOURS: [&str; 2] = ["text", "marked"];
+
+// Diagnostics =============================================
+#[derive(Debug, PartialEq)]
+pub enum Severity {
+    // config can't be used
+    Error,
+    // config works, but probably not the way it was meant to
+    Warning,
+}
+
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub file: PathBuf,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(
+            f,
+            "{}:{}:{}: {}: {}",
+            self.file.display(),
+            self.line,
+            self.column,
+            severity,
+            self.message
+        )
+    }
+}
+
+// collects problems while walking the config
+struct Checker<'a> {
+    file: &'a Path,
+    content: &'a str,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker<'_> {
+    fn push(&mut self, severity: Severity, span: Range<usize>, message: String) {
+        // 1-based line and column of the span start
+        let before = &self.content[..span.start.min(self.content.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before
+            .rsplit('\n')
+            .next()
+            .map(|x| x.chars().count())
+            .unwrap_or(0)
+            + 1;
+
+        self.diagnostics.push(Diagnostic {
+            severity,
+            file: self.file.to_path_buf(),
+            line,
+            column,
+            message,
+        });
+    }
+
+    fn error(&mut self, span: Range<usize>, message: String) {
+        self.push(Severity::Error, span, message);
+    }
+
+    fn warning(&mut self, span: Range<usize>, message: String) {
+        self.push(Severity::Warning, span, message);
+    }
+}
+
+// Spans ===================================================
+// toml::Spanned fails on tables that only exist through [a.b] headers or dotted keys,
+// they have no position of their own and are reported at the start of the file
+struct Spanned<T> {
+    span: Range<usize>,
+    value: T,
+}
+
+impl<T> Spanned<T> {
+    fn span(&self) -> Range<usize> {
+        self.span.clone()
+    }
+
+    fn get_ref(&self) -> &T {
+        &self.value
+    }
+
+    fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Spanned<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        static FIELDS: [&str; 3] = [START_FIELD, END_FIELD, VALUE_FIELD];
+        deserializer.deserialize_struct(NAME, &FIELDS, SpannedVisitor(PhantomData))
+    }
+}
+
+struct SpannedVisitor<T>(PhantomData<T>);
+
+impl<'de, T: Deserialize<'de>> Visitor<'de> for SpannedVisitor<T> {
+    type Value = Spanned<T>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a spanned value")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Spanned<T>, A::Error> {
+        let first = map.next_key::<String>()?;
+        if first.as_deref() != Some(START_FIELD) {
+            let value = T::deserialize(MapAccessDeserializer::new(ImplicitTable { first, map }))?;
+            return Ok(Spanned { span: 0..0, value });
+        }
+
+        let start = map.next_value()?;
+        let (mut end, mut value) = (None, None);
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                END_FIELD => end = Some(map.next_value()?),
+                VALUE_FIELD => value = Some(map.next_value()?),
+                _ => {
+                    return Err(serde::de::Error::unknown_field(
+                        &key,
+                        &[END_FIELD, VALUE_FIELD],
+                    ))
+                }
+            }
+        }
+        match (end, value) {
+            (Some(end), Some(value)) => Ok(Spanned {
+                span: start..end,
+                value,
+            }),
+            (None, _) => Err(serde::de::Error::missing_field(END_FIELD)),
+            (_, None) => Err(serde::de::Error::missing_field(VALUE_FIELD)),
+        }
+    }
+}
+
+// entries of a table without span, the first key was already read by SpannedVisitor
+struct ImplicitTable<A> {
+    first: Option<String>,
+    map: A,
+}
+
+impl<'de, A: MapAccess<'de>> MapAccess<'de> for ImplicitTable<A> {
+    type Error = A::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, A::Error> {
+        match self.first.take() {
+            Some(key) => seed.deserialize(key.into_deserializer()).map(Some),
+            None => self.map.next_key_seed(seed),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, A::Error> {
+        self.map.next_value_seed(seed)
+    }
+}
+
+// Config layout ===========================================
+// everything is kept as spanned values, so one wrong type doesn't hide the other problems
+type SpannedTable = Spanned<HashMap<String, Spanned<Value>>>;
+
+#[derive(Deserialize)]
+struct RawConfig {
+    #[serde(default)]
+    preferences: Option<SpannedTable>,
+    #[serde(default)]
+    keymaps: Option<Spanned<BTreeMap<String, Spanned<RawSection>>>>,
+    #[serde(default)]
+    themes: Option<Spanned<HashMap<String, SpannedTable>>>,
+    #[serde(default)]
+    queue_filter: Option<SpannedTable>,
+}
+
+#[derive(Deserialize)]
+struct RawSection {
+    #[serde(default)]
+    keymap: Vec<SpannedTable>,
+}
+
+// Validation ==============================================
+// every problem in the config, sorted by position
+pub fn check(file: &Path, content: &str) -> Vec<Diagnostic> {
+    let mut checker = Checker {
+        file,
+        content,
+        diagnostics: vec![],
+    };
+
+    // second pass only for names of the top level tables, spans don't survive serde(flatten)
+    let parsed = toml::from_str::<RawConfig>(content).and_then(|config| {
+        toml::from_str::<HashMap<String, Spanned<Value>>>(content).map(|tables| (config, tables))
+    });
+    match parsed {
+        Ok((config, tables)) => check_config(&mut checker, config, tables),
+        Err(e) => {
+            let message = e.message().to_string();
+            checker.error(e.span().unwrap_or(0..0), message);
+        }
+    }
+
+    checker.diagnostics.sort_by_key(|x| (x.line, x.column));
+    checker.diagnostics
+}
+
+fn check_config(checker: &mut Checker, config: RawConfig, tables: HashMap<String, Spanned<Value>>) {
+    for (name, value) in &tables {
+        if TABLES.contains(&name.as_str()) {
+            continue;
+        }
+        let suggestion = suggest(name, &TABLES);
+        checker.warning(
+            value.span(),
+            format!("unknown table `{}`, it is ignored{}", name, suggestion),
+        );
+    }
+
+    let theme_names: Vec<String> = config
+        .themes
+        .as_ref()
+        .map(|x| x.get_ref().keys().cloned().collect())
+        .unwrap_or_default();
+
+    match &config.preferences {
+        Some(preferences) => check_preferences(checker, preferences, &theme_names),
+        None => checker.error(0..0, "missing `[preferences]` table".to_string()),
+    }
+
+    if let Some(queue_filter) = &config.queue_filter {
+        check_queue_filter(checker, queue_filter);
+    }
+
+    match config.keymaps {
+        Some(keymaps) => check_keymaps(checker, keymaps.into_inner()),
+        None => checker.error(0..0, "missing `[keymaps]` table".to_string()),
+    }
+
+    match &config.themes {
+        Some(themes) if !themes.get_ref().is_empty() => {
+            for (name, theme) in themes.get_ref() {
+                check_theme(checker, name, theme);
+            }
+        }
+        Some(themes) => checker.error(themes.span(), "no themes are defined".to_string()),
+        None => checker.error(0..0, "missing `[themes]` table".to_string()),
+    }
+}
+
+fn check_preferences(checker: &mut Checker, preferences: &SpannedTable, theme_names: &[String]) {
+    let table = preferences.get_ref();
+
+    match table.get("selected_theme") {
+        None => checker.error(
+            preferences.span(),
+            "missing `selected_theme` in preferences".to_string(),
+        ),
+        Some(value) => match value.get_ref().as_str() {
+            None => checker.error(
+                value.span(),
+                "`selected_theme` must be a string".to_string(),
+            ),
+            Some(name) if !theme_names.iter().any(|x| x == name) => {
+                let names: Vec<&str> = theme_names.iter().map(|x| x.as_str()).collect();
+                let suggestion = suggest(name, &names);
+                checker.error(
+                    value.span(),
+                    format!("there is no theme named `{}`{}", name, suggestion),
+                );
+            }
+            Some(_) => {}
+        },
+    }
+
+    for (name, value) in table {
+        let span = value.span();
+        let value = value.get_ref();
+        match name.as_str() {
+            "selected_theme" => {}
+            "playlists_folder" | "library_folder" if !value.is_str() => {
+                checker.error(span, format!("`{}` must be a path string", name));
+            }
+            "playlist_format" if value.as_str().and_then(PlaylistFormat::from_str).is_none() => {
+                checker.error(
+                    span,
+                    "`playlist_format` must be one of \"plist\", \"m3u8\", \"xspf\", \"pls\""
+                        .to_string(),
+                );
+            }
+            "auto_resume" | "watch_config" if !value.is_bool() => {
+                checker.error(span, format!("`{}` must be true or false", name));
+            }
+            "key_timeout" if value.as_integer().is_none_or(|x| x < 0) => {
+                checker.error(
+                    span,
+                    "`key_timeout` must be a number of milliseconds".to_string(),
+                );
+            }
+            "autosave_interval" if value.as_integer().is_none_or(|x| x < 0) => {
+                checker.error(
+                    span,
+                    "`autosave_interval` must be a number of seconds".to_string(),
+                );
+            }
+            name if !PREFERENCES.contains(&name) => {
+                let suggestion = suggest(name, &PREFERENCES);
+                checker.warning(span, format!("unknown preference `{}`{}", name, suggestion));
+            }
+            _ => {}
+        }
+    }
+}
+
+// optional table, read by AddAllToQueue
+fn check_queue_filter(checker: &mut Checker, queue_filter: &SpannedTable) {
+    for (name, value) in queue_filter.get_ref() {
+        let span = value.span();
+        let value = value.get_ref();
+        match name.as_str() {
+            "extensions"
+                if value
+                    .as_array()
+                    .is_none_or(|x| x.iter().any(|x| !x.is_str())) =>
+            {
+                checker.error(
+                    span,
+                    "`extensions` must be a list of strings, like [\"mp3\", \"ogg\"]".to_string(),
+                );
+            }
+            "min_duration" if value.as_integer().is_none_or(|x| x < 0) => {
+                checker.error(
+                    span,
+                    "`min_duration` must be a number of seconds".to_string(),
+                );
+            }
+            "pattern" if !value.is_str() => {
+                checker.error(
+                    span,
+                    "`pattern` must be a string with `*` and `?`, like \"*live*\"".to_string(),
+                );
+            }
+            "pattern" if value.as_str() == Some("") => {
+                checker.warning(span, "empty `pattern` matches no file".to_string());
+            }
+            name if !QUEUE_FILTER.contains(&name) => {
+                let suggestion = suggest(name, &QUEUE_FILTER);
+                checker.warning(
+                    span,
+                    format!("unknown queue filter `{}`{}", name, suggestion),
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
+fn check_keymaps(checker: &mut Checker, keymaps: BTreeMap<String, Spanned<RawSection>>) {
+    // same order as Parser::parse_keys, sections sorted by name, so the later binding wins
+    let mut entries: Vec<(Context, SpannedTable)> = vec![];
+    for (name, section) in keymaps {
+        let context = Context::from_str(&name).unwrap_or_else(|| {
+            let suggestion = suggest(&name, &CONTEXT_NAMES);
+            if !suggestion.is_empty() {
+                checker.warning(
+                    section.span(),
+                    format!(
+                        "`{}` isn't a context, its keys work everywhere{}",
+                        name, suggestion
+                    ),
+                );
+            }
+            Context::Global
+        });
+        entries.extend(
+            section
+                .into_inner()
+                .keymap
+                .into_iter()
+                .map(|entry| (context, entry)),
+        );
+    }
+
+    // keys in a context -> action and span of the binding seen first
+    let mut bound: HashMap<(Context, Vec<KeyEvent>), (String, Range<usize>)> = HashMap::new();
+
+    for (context, entry) in entries {
+        let span = entry.span();
+        let entry = entry.into_inner();
+
+        let action = match entry.get("action") {
+            None => {
+                checker.error(span.clone(), "keymap entry has no `action`".to_string());
+                None
+            }
+            Some(action) => check_action(checker, action, entry.get("arg")),
+        };
+
+        let keys = match entry.get("key") {
+            None => {
+                checker.error(span.clone(), "keymap entry has no `key`".to_string());
+                None
+            }
+            Some(key) => match key.get_ref().as_str() {
+                None => {
+                    checker.error(key.span(), "`key` must be a string".to_string());
+                    None
+                }
+                Some(name) => {
+                    let keys = parse_keys(name);
+                    if keys.is_none() {
+                        let suggestion = suggest(name, &key_names());
+                        checker.warning(
+                            key.span(),
+                            format!(
+                                "unknown key `{}`, the binding is ignored{}",
+                                name, suggestion
+                            ),
+                        );
+                    }
+                    check_sequence(checker, key, name);
+                    keys
+                }
+            },
+        };
+
+        let mut modifiers = KeyModifiers::empty();
+        if let Some(mods) = entry.get("mods") {
+            let names: Vec<Option<&str>> = match mods.get_ref().as_array() {
+                Some(array) => array.iter().map(|x| x.as_str()).collect(),
+                None => vec![None],
+            };
+            for name in names {
+                match name.map(|x| (x, parse_modifier(x))) {
+                    Some((_, Some(modifier))) => modifiers.insert(modifier),
+                    Some((name, None)) => {
+                        let suggestion = suggest(name, &modifier_names());
+                        checker.warning(
+                            mods.span(),
+                            format!("unknown modifier `{}`{}", name, suggestion),
+                        );
+                    }
+                    None => checker.error(
+                        mods.span(),
+                        "`mods` must be a list of strings, like [\"CTRL\"]".to_string(),
+                    ),
+                }
+            }
+        }
+
+        if let (Some(mut keys), Some(action)) = (keys, action) {
+            keys.iter_mut()
+                .for_each(|key| key.modifiers.insert(modifiers));
+            let keys = (context, keys);
+            if let Some((other_action, other_span)) = bound.get(&keys) {
+                let other_span = other_span.clone();
+                let other_line = checker.content[..other_span.start].matches('\n').count() + 1;
+                let message = format!(
+                    "key is already bound to `{}` on line {}, this binding replaces it",
+                    other_action, other_line
+                );
+                checker.warning(span.clone(), message);
+            }
+            bound.insert(keys, (action, span));
+        }
+    }
+}
+
+// "Escp" is read as four keys, which is probably not what was meant
+fn check_sequence(checker: &mut Checker, key: &Spanned<Value>, name: &str) {
+    for word in name.split_whitespace() {
+        if parse_key(word).is_some()
+            || word.chars().count() < 3
+            || !word.chars().any(|x| x.is_ascii_uppercase())
+        {
+            continue;
+        }
+
+        let suggestion = suggest(word, &key_names());
+        if !suggestion.is_empty() {
+            checker.warning(
+                key.span(),
+                format!(
+                    "`{}` is read as {} separate keys{}",
+                    word,
+                    word.chars().count(),
+                    suggestion
+                ),
+            );
+        }
+    }
+}
+
+// the action still takes the key when it is unknown, it is bound as a no-op then
+fn check_action(
+    checker: &mut Checker,
+    action: &Spanned<Value>,
+    arg: Option<&Spanned<Value>>,
+) -> Option<String> {
+    let Some(name) = action.get_ref().as_str() else {
+        checker.error(action.span(), "`action` must be a string".to_string());
+        return None;
+    };
+
+    let takes_arg = arg_action_names().contains(&name);
+    if !takes_arg && !action_names().contains(&name) {
+        let names: Vec<&str> = action_names()
+            .into_iter()
+            .chain(arg_action_names())
+            .collect();
+        let suggestion = suggest(name, &names);
+        checker.warning(
+            action.span(),
+            format!(
+                "unknown action `{}`, the key does nothing{}",
+                name, suggestion
+            ),
+        );
+        return Some(name.to_string());
+    }
+
+    match arg {
+        Some(arg) if !takes_arg => {
+            checker.warning(
+                arg.span(),
+                format!("`{}` doesn't take an `arg`, the key does nothing", name),
+            );
+        }
+        Some(arg) if arg.get_ref().as_integer().is_none_or(|x| x < 0) => {
+            checker.error(arg.span(), "`arg` must be a positive number".to_string());
+        }
+        None if takes_arg => {
+            checker.warning(
+                action.span(),
+                format!("`{}` needs an `arg`, the key does nothing", name),
+            );
+        }
+        _ => {}
+    }
+
+    Some(name.to_string())
+}
+
+fn check_theme(checker: &mut Checker, name: &str, theme: &SpannedTable) {
+    let colours = theme.get_ref();
+
+    for colour in THEME_COLOURS {
+        if !colours.contains_key(colour) {
+            checker.error(
+                theme.span(),
+                format!("theme `{}` has no `{}` colour", name, colour),
+            );
+        }
+    }
+    if !colours.contains_key("text") {
+        checker.warning(
+            theme.span(),
+            format!("theme `{}` has no `text` colour, black is used", name),
+        );
+    }
+
+    for (colour, value) in colours {
+        if !THEME_COLOURS.contains(&colour.as_str())
+            && !OPTIONAL_THEME_COLOURS.contains(&colour.as_str())
+        {
+            let names: Vec<&str> = THEME_COLOURS
+                .iter()
+                .chain(&OPTIONAL_THEME_COLOURS)
+                .copied()
+                .collect();
+            let suggestion = suggest(colour, &names);
+            checker.warning(
+                value.span(),
+                format!(
+                    "unknown colour `{}` in theme `{}`{}",
+                    colour, name, suggestion
+                ),
+            );
+            continue;
+        }
+
+        let is_rgb = value.get_ref().as_array().is_some_and(|array| {
+            array.len() == 3
+                && array
+                    .iter()
+                    .all(|x| x.as_integer().is_some_and(|x| (0..=255).contains(&x)))
+        });
+        if !is_rgb {
+            checker.error(
+                value.span(),
+                format!("`{}` must be [red, green, blue] with values 0-255", colour),
+            );
+        }
+    }
+}
+
+// Suggestions =============================================
+// " (did you mean `ToggleRepeat`?)" for the closest name, empty when nothing is close
+pub fn suggest(name: &str, candidates: &[&str]) -> String {
+    let name = name.to_lowercase();
+    let limit = (name.chars().count() / 3).max(2);
+
+    candidates
+        .iter()
+        .map(|candidate| (distance(&name, &candidate.to_lowercase()), candidate))
+        .filter(|(distance, _)| *distance <= limit)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| format!(" (did you mean `{}`?)", candidate))
+        .unwrap_or_default()
+}
+
+// Levenshtein distance
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, x) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, y) in b.iter().enumerate() {
+            let cost = if x == *y { 0 } else { 1 };
+            current.push(
+                (previous[j] + cost)
+                    .min(previous[j + 1] + 1)
+                    .min(current[j] + 1),
+            );
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THEME: &str = r#"
+[themes.dark]
+text = [255, 255, 255]
+text_headline = [255, 255, 255]
+background = [0, 0, 0]
+border = [255, 255, 255]
+music = [0, 255, 0]
+progress_bar_elapsed = [0, 0, 255]
+highlighted = [255, 0, 0]
+directories = [255, 255, 0]
+error = [255, 0, 0]
+error_text = [0, 0, 0]
+"#;
+
+    // minimal valid config with `extra` before the themes,
+    // [keymaps] and [themes] only exist through the headers below them
+    fn config(extra: &str) -> String {
+        format!(
+            "[preferences]\nselected_theme = \"dark\"\n\n[keymaps.global]\nkeymap = [\n    {{ key = \"q\", action = \"Exit\" }},\n]\n{}{}",
+            extra, THEME
+        )
+    }
+
+    fn diagnostics(content: &str) -> Vec<(Severity, usize, usize, String)> {
+        check(Path::new("config.toml"), content)
+            .into_iter()
+            .map(|x| (x.severity, x.line, x.column, x.message))
+            .collect()
+    }
+
+    #[test]
+    fn valid_config() {
+        assert!(diagnostics(&config("")).is_empty());
+    }
+
+    #[test]
+    fn sample_config_has_no_errors() {
+        let content = include_str!("../config_sample/config.toml");
+        let errors: Vec<_> = check(Path::new("config.toml"), content)
+            .into_iter()
+            .filter(|x| x.severity == Severity::Error)
+            .map(|x| x.to_string())
+            .collect();
+        assert_eq!(errors, Vec::<String>::new());
+    }
+
+    #[test]
+    fn unknown_theme() {
+        let content = config("").replace("\"dark\"", "\"drak\"");
+        assert_eq!(
+            diagnostics(&content),
+            [(
+                Severity::Error,
+                2,
+                18,
+                "there is no theme named `drak` (did you mean `dark`?)".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn unknown_action_is_a_warning() {
+        let content = config("").replace("Exit", "Exitt");
+        let diagnostics = diagnostics(&content);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].0, Severity::Warning);
+        assert_eq!((diagnostics[0].1, diagnostics[0].2), (6, 27));
+        assert!(diagnostics[0].3.starts_with("unknown action `Exitt`"));
+    }
+
+    #[test]
+    fn missing_tables() {
+        let messages: Vec<String> = diagnostics("")
+            .into_iter()
+            .map(|(severity, line, column, message)| {
+                assert_eq!((severity, line, column), (Severity::Error, 1, 1));
+                message
+            })
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "missing `[preferences]` table",
+                "missing `[keymaps]` table",
+                "missing `[themes]` table"
+            ]
+        );
+    }
+
+    #[test]
+    fn syntax_error() {
+        let diagnostics = diagnostics("[preferences\n");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!((&diagnostics[0].0, diagnostics[0].1), (&Severity::Error, 1));
+    }
+
+    #[test]
+    fn queue_filter_types() {
+        let content = config(
+            "\n[queue_filter]\nextensions = \"mp3\"\nmin_duration = -1\npattern = 5\nextension = []\n",
+        );
+        let diagnostics = diagnostics(&content);
+        let lines: Vec<(&Severity, usize, &str)> = diagnostics
+            .iter()
+            .map(|(severity, line, _, message)| (severity, *line, message.as_str()))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                (
+                    &Severity::Error,
+                    10,
+                    "`extensions` must be a list of strings, like [\"mp3\", \"ogg\"]"
+                ),
+                (
+                    &Severity::Error,
+                    11,
+                    "`min_duration` must be a number of seconds"
+                ),
+                (
+                    &Severity::Error,
+                    12,
+                    "`pattern` must be a string with `*` and `?`, like \"*live*\""
+                ),
+                (
+                    &Severity::Warning,
+                    13,
+                    "unknown queue filter `extension` (did you mean `extensions`?)"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn implicit_tables() {
+        let content = config("\n[[keymaps.globl.keymap]]\nkey = \"p\"\naction = \"TogglePause\"\n")
+            .replace(
+                "selected_theme",
+                "themes.light.music = [0, 0, 0]\nselected_theme",
+            );
+        // they have no span of their own
+        assert_eq!(
+            diagnostics(&content),
+            [
+                (
+                    Severity::Warning,
+                    1,
+                    1,
+                    "unknown preference `themes`".to_string()
+                ),
+                (
+                    Severity::Warning,
+                    1,
+                    1,
+                    "`globl` isn't a context, its keys work everywhere (did you mean `global`?)"
+                        .to_string()
+                ),
+            ]
+        );
+    }
+}
@@ -1,49 +1,93 @@
 #![allow(dead_code, unused_variables)]
 use std::{
     cmp::Ordering,
-    collections::{HashMap, VecDeque},
-    env, fs,
-    path::PathBuf,
-    sync::{Arc, RwLock},
+    collections::{BTreeSet, HashMap, VecDeque},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
 };
 
 use anyhow::{anyhow, Ok, Result};
+use ratatui::layout::Rect;
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    command::CommandLine,
     config::Config,
-    player::{self, Player},
+    formats::{
+        backup, header, parse_m3u, parse_plist, parse_pls, parse_xspf, read_versioned,
+        write_atomic, write_m3u, write_plist, write_pls, write_xspf, PlaylistFile, PlaylistFormat,
+        PlaylistTrack, M3U8_FILE_EXT, M3U_FILE_EXT, PLS_FILE_EXT, STATE_FORMAT, XSPF_FILE_EXT,
+    },
+    history::History,
+    keymap::Context,
+    player::Player,
+    smart::SMART_PLAYLIST_FILE_EXT,
 };
 
 pub const PLAYLIST_FILE_EXT: &str = "plist";
 
 // Structure for saving state ==============================
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
 pub struct Saver {
     pub queue: VecDeque<PathBuf>,
     pub song_index: usize,
     pub volume: f32,
     pub selected_theme: String,
+    // position in the current track
+    pub position_ms: u64,
+    pub paused: bool,
+    pub repeat: bool,
+    pub tree_view: TreeState,
+    pub play_counts: HashMap<PathBuf, usize>,
 }
 
-impl Saver {
-    pub fn default() -> Self {
-        Saver {
-            queue: VecDeque::new(),
-            song_index: 0,
-            volume: 0.0,
-            selected_theme: String::new(),
-        }
-    }
+// layout of musicshell.dat before the versioned format
+#[derive(Deserialize)]
+struct SaverV1 {
+    queue: VecDeque<PathBuf>,
+    song_index: usize,
+    volume: f32,
+    selected_theme: String,
+}
 
+impl Saver {
     pub fn save(
         &mut self,
         player: Arc<RwLock<Player>>,
         workspace: Arc<RwLock<Workspace>>,
-        save_file: &PathBuf,
+        save_file: &Path,
+    ) -> Result<()> {
+        {
+            let mutex = player.read().unwrap();
+            let mutex_workspace = workspace.read().unwrap();
+            self.collect(&mutex, &mutex_workspace);
+        }
+
+        self.write(save_file)
+    }
+
+    // for the panic hook, the panicking thread may still hold one of the locks
+    pub fn try_save(
+        &mut self,
+        player: &Arc<RwLock<Player>>,
+        workspace: &Arc<RwLock<Workspace>>,
+        save_file: &Path,
     ) -> Result<()> {
-        let mutex = player.read().unwrap();
-        let mutex_workspace = workspace.read().unwrap();
+        {
+            let (std::result::Result::Ok(mutex), std::result::Result::Ok(mutex_workspace)) =
+                (player.try_read(), workspace.try_read())
+            else {
+                return Err(anyhow!("State is locked, last autosave is kept"));
+            };
+            self.collect(&mutex, &mutex_workspace);
+        }
+
+        self.write(save_file)
+    }
+
+    fn collect(&mut self, mutex: &Player, mutex_workspace: &Workspace) {
         self.queue = mutex.queue.clone();
         self.song_index = if mutex.song_index != 0 {
             mutex.song_index - 1
@@ -52,33 +96,127 @@ impl Saver {
         };
         self.volume = mutex.get_volume();
         self.selected_theme = mutex_workspace.config.selected_theme.clone();
+        self.position_ms = if mutex.is_empty() {
+            0
+        } else {
+            mutex.get_position().as_millis() as u64
+        };
+        self.paused = mutex.is_paused();
+        self.repeat = mutex.repeat;
+        // editor isn't restored, its playlist is still in playlists view
+        self.tree_view = match mutex_workspace.tree.state {
+            TreeState::Editor => TreeState::Playlists,
+            ref state => state.clone(),
+        };
+        self.play_counts = mutex.play_counts.clone();
+    }
 
-        let encoded: Vec<u8> = bincode::serialize(&self)?;
-        fs::write(save_file, encoded)?;
+    // autosave, signal handlers and Exit can save at the same time
+    fn write(&self, save_file: &Path) -> Result<()> {
+        static WRITE_LOCK: Mutex<()> = Mutex::new(());
+        let _guard = WRITE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
 
-        Ok(())
+        let content = header(STATE_FORMAT) + &toml::to_string(self)?;
+        write_atomic(save_file, content.as_bytes())
     }
 
-    pub fn save_playlist(save_dir: &PathBuf, name: String, queue: VecDeque<PathBuf>) -> Result<()> {
-        let encoded = bincode::serialize(&queue)?;
+    pub fn save_playlist(
+        save_dir: &Path,
+        name: String,
+        queue: VecDeque<PathBuf>,
+        format: PlaylistFormat,
+    ) -> Result<()> {
+        let new_path = save_dir.join(name + "." + format.extension());
+        let tracks: Vec<PlaylistTrack> = queue
+            .into_iter()
+            .map(|path| {
+                let track = PlaylistTrack::new(path);
+                if format.stores_tags() {
+                    track.with_info()
+                } else {
+                    track.with_size()
+                }
+            })
+            .collect();
 
-        let new_path = save_dir.join(name + "." + PLAYLIST_FILE_EXT);
-        fs::write(new_path, encoded)
-            .map_err(|e| anyhow!("Problem with playlists folder:\n{}", e))?;
+        Saver::write_playlist(&new_path, tracks, format)
+    }
 
-        Ok(())
+    pub fn write_playlist(
+        path: &Path,
+        tracks: Vec<PlaylistTrack>,
+        format: PlaylistFormat,
+    ) -> Result<()> {
+        match format {
+            PlaylistFormat::Plist => {
+                let mut playlist = PlaylistFile::new(tracks);
+
+                // saving over an existing playlist keeps its description and creation date
+                if let std::result::Result::Ok(old) = parse_plist(path) {
+                    playlist.description = old.description;
+                    playlist.created = old.created;
+                }
+                write_plist(path, &playlist)
+            }
+            PlaylistFormat::Xspf => write_xspf(path, &tracks),
+            PlaylistFormat::Pls => write_pls(path, &tracks),
+            PlaylistFormat::M3u8 => write_m3u(path, &tracks),
+        }
     }
 
-    pub fn restore(save_file: &PathBuf) -> Result<Self> {
-        let encoded: Vec<u8> = fs::read(save_file)?;
-        let mut decoded: Saver = bincode::deserialize(&encoded)?;
+    // writes playlist next to the original one, returns path of the new file
+    pub fn export_playlist(playlist: &Playlist, format: PlaylistFormat) -> Result<PathBuf> {
+        if playlist.kind == PlaylistKind::Smart {
+            return Err(anyhow!("Smart playlists can't be exported"));
+        }
+
+        let save_dir = playlist.path.parent().unwrap_or(Path::new(""));
+        let new_path = save_dir.join(playlist.name.clone() + "." + format.extension());
+        if new_path == playlist.path {
+            return Err(anyhow!("Playlist is already in this format"));
+        }
+        if new_path.exists() {
+            return Err(anyhow!("File {} already exists", new_path.display()));
+        }
+
+        let queue = Saver::restore_playlist(&playlist.path)?;
+        Saver::save_playlist(save_dir, playlist.name.clone(), queue, format)?;
+
+        Ok(new_path)
+    }
 
-        decoded.queue.retain(|path| path.exists());
+    // state from older versions is converted, the original file is kept as .bak
+    pub fn restore(save_file: &Path) -> Result<Self> {
+        let content: Vec<u8> = fs::read(save_file)?;
+        if let Some(table) = read_versioned(&content, STATE_FORMAT)? {
+            return Ok(table.try_into()?);
+        }
+
+        let decoded: SaverV1 = bincode::deserialize(&content)?;
+        let mut saver = Saver {
+            queue: decoded.queue,
+            song_index: decoded.song_index,
+            volume: decoded.volume,
+            selected_theme: decoded.selected_theme,
+            ..Default::default()
+        };
 
-        Ok(decoded)
+        // play counts were kept in a separate bincode file
+        let stats_file = save_file.with_extension("stats");
+        if let std::result::Result::Ok(encoded) = fs::read(&stats_file)
+            && let std::result::Result::Ok(play_counts) = bincode::deserialize(&encoded)
+        {
+            saver.play_counts = play_counts;
+            fs::rename(&stats_file, stats_file.with_extension("stats.bak"))?;
+        }
+
+        backup(save_file)?;
+        saver.write(save_file)?;
+
+        Ok(saver)
     }
 
-    pub fn restore_playlists(workspace: Arc<RwLock<Workspace>>, dir: &PathBuf) -> Result<()> {
+    pub fn restore_playlists(workspace: Arc<RwLock<Workspace>>, dir: &Path) -> Result<()> {
         let mut mutex = workspace.write().unwrap();
         mutex.tree.playlists.clear();
 
@@ -88,33 +226,187 @@ impl Saver {
             }
 
             let entry = entry.unwrap().path();
-            if entry.exists()
-                && entry.extension().is_some()
-                && entry.extension().unwrap().to_str().unwrap() == PLAYLIST_FILE_EXT
-            {
-                let name = entry.file_stem().unwrap().to_str().unwrap();
-                mutex.tree.playlists.push(name.to_string());
+            let Some(kind) = PlaylistKind::from_path(&entry) else {
+                continue;
+            };
+
+            if entry.exists() {
+                let name = entry.file_stem().unwrap().to_str().unwrap().to_string();
+                let info = if kind == PlaylistKind::Native {
+                    parse_plist(&entry).ok().map(|mut x| {
+                        x.tracks.clear();
+                        x
+                    })
+                } else {
+                    None
+                };
+
+                mutex.tree.playlists.push(Playlist {
+                    name,
+                    path: entry,
+                    kind,
+                    info,
+                });
             }
         }
+        mutex.tree.playlists.sort_by(|a, b| a.name.cmp(&b.name));
+        if mutex.tree.state == TreeState::Playlists {
+            mutex.tree.clear_marks();
+        }
 
         Ok(())
     }
 
-    pub fn restore_playlist(name: &str, dir: &PathBuf) -> Result<VecDeque<PathBuf>> {
-        for entry in dir.read_dir()? {
-            if entry.is_err() {
-                continue;
-            }
+    pub fn restore_playlist(path: &Path) -> Result<VecDeque<PathBuf>> {
+        let tracks = Saver::restore_playlist_tracks(path)?;
+        Ok(tracks.into_iter().map(|track| track.path).collect())
+    }
 
-            let entry = entry.unwrap().path();
-            if entry.exists() && entry.file_stem().unwrap().to_str().unwrap() == name {
-                let encoded = fs::read(entry)?;
-                return bincode::deserialize(&encoded)
-                    .map_err(|e| anyhow!("Error occured while parsing file:\n{e}"));
-            }
+    pub fn restore_playlist_tracks(path: &Path) -> Result<Vec<PlaylistTrack>> {
+        if !path.exists() {
+            return Err(anyhow!("Playlist is not found in filesystem"));
+        }
+
+        match path.extension().and_then(|x| x.to_str()) {
+            Some(M3U_FILE_EXT) | Some(M3U8_FILE_EXT) => parse_m3u(path),
+            Some(XSPF_FILE_EXT) => parse_xspf(path),
+            Some(PLS_FILE_EXT) => parse_pls(path),
+            _ => Ok(parse_plist(path)?.tracks),
+        }
+    }
+}
+
+// Playlists section =======================================
+#[derive(PartialEq, Clone)]
+pub enum PlaylistKind {
+    Native,
+    Smart,
+    M3u,
+    Xspf,
+    Pls,
+}
+
+impl PlaylistKind {
+    // None for files that aren't playlists
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension().and_then(|x| x.to_str()) {
+            Some(PLAYLIST_FILE_EXT) => Some(PlaylistKind::Native),
+            Some(SMART_PLAYLIST_FILE_EXT) => Some(PlaylistKind::Smart),
+            Some(M3U_FILE_EXT) | Some(M3U8_FILE_EXT) => Some(PlaylistKind::M3u),
+            Some(XSPF_FILE_EXT) => Some(PlaylistKind::Xspf),
+            Some(PLS_FILE_EXT) => Some(PlaylistKind::Pls),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Playlist {
+    pub name: String,
+    pub path: PathBuf,
+    pub kind: PlaylistKind,
+    // description and dates of native playlists, tracks are not kept here
+    pub info: Option<PlaylistFile>,
+}
+
+// Playlist editor section =================================
+pub struct PlaylistEditor {
+    pub playlist: Playlist,
+    pub tracks: Vec<PlaylistTrack>,
+    pub modified: bool,
+}
+
+impl PlaylistEditor {
+    pub fn open(playlist: &Playlist) -> Result<Self> {
+        if playlist.kind == PlaylistKind::Smart {
+            return Err(anyhow!("Smart playlists are edited in their rule file"));
+        }
+
+        let tracks = Saver::restore_playlist_tracks(&playlist.path)?
+            .into_iter()
+            .map(|track| track.with_info())
+            .collect();
+
+        Ok(PlaylistEditor {
+            playlist: playlist.clone(),
+            tracks,
+            modified: false,
+        })
+    }
+
+    pub fn total_duration(&self) -> usize {
+        self.tracks.iter().filter_map(|track| track.duration).sum()
+    }
+
+    // returns new position of the track
+    pub fn move_track(&mut self, index: usize, up: bool) -> usize {
+        if index >= self.tracks.len() {
+            return index;
+        }
+
+        let new_index = if up {
+            index.saturating_sub(1)
+        } else {
+            (index + 1).min(self.tracks.len() - 1)
+        };
+        if new_index != index {
+            self.tracks.swap(index, new_index);
+            self.modified = true;
+        }
+
+        new_index
+    }
+
+    pub fn move_to_top(&mut self, index: usize) {
+        if index != 0 && index < self.tracks.len() {
+            let track = self.tracks.remove(index);
+            self.tracks.insert(0, track);
+            self.modified = true;
+        }
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        if index < self.tracks.len() {
+            self.tracks.remove(index);
+            self.modified = true;
+        }
+    }
+
+    pub fn append(&mut self, path: PathBuf) {
+        self.tracks.push(PlaylistTrack::new(path).with_info());
+        self.modified = true;
+    }
+
+    // written in the format the playlist already has
+    pub fn save(&mut self) -> Result<()> {
+        let format =
+            PlaylistFormat::from_path(&self.playlist.path).unwrap_or(PlaylistFormat::Plist);
+        Saver::write_playlist(&self.playlist.path, self.tracks.clone(), format)?;
+        self.modified = false;
+
+        Ok(())
+    }
+
+    // "save as", editor switches to the new file
+    pub fn save_as(&mut self, name: String) -> Result<()> {
+        let extension = self
+            .playlist
+            .path
+            .extension()
+            .and_then(|x| x.to_str())
+            .unwrap_or(PLAYLIST_FILE_EXT)
+            .to_string();
+        let path = self
+            .playlist
+            .path
+            .with_file_name(name.clone() + "." + &extension);
+        if path.exists() {
+            return Err(anyhow!("File {} already exists", path.display()));
         }
 
-        Err(anyhow!("Playlist is not found in filesystem"))
+        self.playlist.name = name;
+        self.playlist.path = path;
+        self.save()
     }
 }
 
@@ -124,7 +416,28 @@ pub enum Windows {
     None,
     ThemeSelect,
     PlaylistSave,
+    PlaylistRename,
+    PlaylistDuplicate,
+    RelinkPrefix,
+    Report(String, Vec<String>),
+    Notice(String),
+    // yes or no question, answered with any key
+    Confirm(String),
     Error(String),
+    // keymap of the context it was opened from, filter is in stdin_buffer
+    Help(Context),
+    // typed line is in stdin_buffer
+    Command,
+}
+
+// where ui drew the parts that take mouse clicks, updated every frame
+#[derive(Default, Clone, Copy, PartialEq)]
+pub struct Areas {
+    // tree list with its border and the first row shown
+    pub list: Rect,
+    pub list_offset: usize,
+    // statusbar block around the progress bar
+    pub progress: Rect,
 }
 
 pub struct Workspace {
@@ -133,11 +446,19 @@ pub struct Workspace {
     pub running: bool,
     pub window: Windows,
     pub stdin_buffer: String,
+    pub editor: Option<PlaylistEditor>,
+    pub popup_selected: usize,
+    pub history: History,
+    // count and keys of an unfinished sequence, shown in statusbar
+    pub pending_keys: String,
+    pub command: CommandLine,
+    pub areas: Areas,
 }
 
 impl Workspace {
-    pub fn new(config: Config) -> Result<Self> {
-        let tree = Tree::new()?;
+    // cwd is the directory shown in files view
+    pub fn new(config: Config, cwd: PathBuf) -> Result<Self> {
+        let tree = Tree::new(cwd)?;
 
         Ok(Workspace {
             config,
@@ -145,32 +466,48 @@ impl Workspace {
             running: true,
             window: Windows::None,
             stdin_buffer: String::new(),
+            editor: None,
+            popup_selected: 0,
+            history: History::new(),
+            pending_keys: String::new(),
+            command: CommandLine::default(),
+            areas: Areas::default(),
         })
     }
 }
 
 // Tree section =============================================
-#[derive(PartialEq, Clone)]
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum TreeState {
+    #[default]
     Files,
     Queue,
     Playlists,
+    Editor,
 }
 
 impl TreeState {
-    pub fn next(&self) -> Self {
+    // editor view is only reachable while a playlist is open in it
+    pub fn next(&self, editor_open: bool) -> Self {
         if *self == Self::Files {
             Self::Queue
         } else if *self == Self::Queue {
             Self::Playlists
+        } else if *self == Self::Playlists && editor_open {
+            Self::Editor
         } else {
             Self::Files
         }
     }
 
-    pub fn prev(&self) -> Self {
+    pub fn prev(&self, editor_open: bool) -> Self {
         if *self == Self::Playlists {
             Self::Queue
+        } else if *self == Self::Editor {
+            Self::Playlists
+        } else if *self == Self::Files && editor_open {
+            Self::Editor
         } else if *self == Self::Files {
             Self::Playlists
         } else {
@@ -182,14 +519,17 @@ impl TreeState {
 pub struct Tree {
     pub cwd: PathBuf,
     pub path_list: Vec<PathBuf>,
-    pub playlists: Vec<String>,
+    pub playlists: Vec<Playlist>,
     pub selected: usize,
     pub state: TreeState,
+    // marked rows of the current list, cleared whenever the list changes
+    pub marked: BTreeSet<usize>,
+    // start of the visual range, the range ends at selected
+    pub visual_start: Option<usize>,
 }
 
 impl Tree {
-    pub fn new() -> Result<Self> {
-        let cwd = env::current_dir()?;
+    pub fn new(cwd: PathBuf) -> Result<Self> {
         let mut path_list: Vec<PathBuf> = cwd
             .read_dir()?
             .filter_map(|entry| entry.ok().map(|e| e.path()))
@@ -202,8 +542,35 @@ impl Tree {
             selected: 0,
             state: TreeState::Files,
             playlists: vec![],
+            marked: BTreeSet::new(),
+            visual_start: None,
         })
     }
+
+    pub fn is_marked(&self, index: usize) -> bool {
+        self.marked.contains(&index)
+            || self.visual_start.is_some_and(|start| {
+                start.min(self.selected) <= index && index <= start.max(self.selected)
+            })
+    }
+
+    // marked rows and visual range in order, or just the selected row
+    pub fn selection(&self, list_len: usize) -> Vec<usize> {
+        let mut selection: BTreeSet<usize> = self.marked.clone();
+        if let Some(start) = self.visual_start {
+            selection.extend(start.min(self.selected)..=start.max(self.selected));
+        }
+        if selection.is_empty() {
+            selection.insert(self.selected);
+        }
+
+        selection.into_iter().filter(|&x| x < list_len).collect()
+    }
+
+    pub fn clear_marks(&mut self) {
+        self.marked.clear();
+        self.visual_start = None;
+    }
 }
 
 // Sorting implementation